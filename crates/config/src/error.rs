@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read the configuration file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse the configuration file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid environment variable {var}: {reason}")]
+    Env { var: &'static str, reason: String },
+    #[error("`{0}` must be a section of the configuration file")]
+    NotASection(&'static str),
+    #[error("Unknown section [{0}] in the configuration file")]
+    UnknownSection(String),
+    #[error("Invalid [{section}] configuration: {reason} (its keys can also be set with {hint})")]
+    Section {
+        section: &'static str,
+        hint: String,
+        reason: String,
+    },
+    #[error("Missing [{section}] configuration, set it in the configuration file or with {hint}")]
+    MissingSection { section: &'static str, hint: String },
+    #[error("Invalid {key}: {reason}")]
+    Invalid { key: &'static str, reason: String },
+}
@@ -0,0 +1,656 @@
+//! Configuration shared by the backend, the database tool and the frontend.
+//!
+//! Settings are read from a TOML file, then overridden by environment variables (which keep the
+//! names used by the `.env` files). Sections are validated as they are loaded, but only the
+//! sections a binary asks for are required to be present.
+
+mod error;
+
+pub use error::ConfigError;
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+/// Environment variable pointing to the configuration file
+pub const CONFIG_PATH_ENV: &str = "PASHE_CONFIG";
+/// Configuration file used when none is given, it is optional
+pub const DEFAULT_CONFIG_PATH: &str = "pashe.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClickhouseConfig {
+    pub url: String,
+    pub user: String,
+    pub password: String,
+    pub database: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Endpoint issuing the access tokens
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+}
+
+fn default_token_url() -> String {
+    "https://www.pathofexile.com/oauth/token".to_string()
+}
+
+/// Where the OAuth access token is cached, selected by the `backend` key
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TokenCacheConfig {
+    /// Shared with other instances through the [redis] server
+    #[default]
+    Redis,
+    /// File encrypted with a 256-bit key, given as 64 hexadecimal characters
+    EncryptedFile { path: PathBuf, key: String },
+    /// Lost when the process exits
+    Memory,
+}
+
+/// Where the rate limit state is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStoreConfig {
+    /// Private to the process and lost on restart
+    #[default]
+    Memory,
+    /// Shared by every process using the same OAuth client, requires [redis]
+    Redis,
+}
+
+/// How requests are spread within the rate limit budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitPacingConfig {
+    /// Send requests as soon as a hit is left, then wait for the window to reset
+    #[default]
+    Burst,
+    /// Spread the hits left evenly over the time left in the windows
+    Smooth,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlerConfig {
+    /// Realms to crawl, each one with its own change ID stream
+    pub realms: Vec<String>,
+    /// Base URL of the Path of Exile API
+    pub api_url: String,
+    /// Number of decoded pages that can wait for the processor before the crawler slows down
+    pub queue_depth: NonZeroUsize,
+    /// Maximum number of pages being fetched concurrently
+    pub max_in_flight: NonZeroUsize,
+    /// Seconds without an ingested page after which the backend reports itself unhealthy
+    pub stall_timeout: u64,
+    pub rate_limit_store: RateLimitStoreConfig,
+    pub rate_limit_pacing: RateLimitPacingConfig,
+    /// Attempts at fetching a page on server and network errors before it is dead-lettered
+    pub max_attempts: NonZeroU32,
+    /// Stashes whose last contents are kept in memory to diff their updates, the least recently
+    /// updated ones are evicted and read back from the database when they change again
+    pub snapshot_capacity: NonZeroUsize,
+    /// Stat templates of the trade site, matched against the mods of the items. The bundled file
+    /// is a starter subset of the trade site's `/api/trade/data/stats` response
+    pub stats_file: PathBuf,
+    /// Catalogue of the tradable currencies and their aliases in price notes. The bundled file is
+    /// a hand-picked partial list of the currencies of the trade site's `/api/trade/data/static`
+    /// response, listings priced in the others are kept with an unknown currency
+    pub currencies_file: PathBuf,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> Self {
+        Self {
+            realms: vec!["pc".to_string()],
+            api_url: "https://api.pathofexile.com".to_string(),
+            queue_depth: NonZeroUsize::new(8).unwrap(),
+            max_in_flight: NonZeroUsize::new(4).unwrap(),
+            stall_timeout: 300,
+            rate_limit_store: RateLimitStoreConfig::default(),
+            rate_limit_pacing: RateLimitPacingConfig::default(),
+            max_attempts: NonZeroU32::new(5).unwrap(),
+            snapshot_capacity: NonZeroUsize::new(200_000).unwrap(),
+            stats_file: PathBuf::from("data/stats.json"),
+            currencies_file: PathBuf::from("data/currencies.json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Archive every fetched page to this directory, keyed by realm and change ID
+    pub archive_dir: Option<PathBuf>,
+    /// Directory logging the change IDs that could not be fetched and keeping the pages that could
+    /// not be parsed, keyed by realm
+    pub dead_letter_dir: PathBuf,
+    /// Address serving the Prometheus metrics and the health endpoints
+    pub metrics_address: SocketAddr,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            archive_dir: None,
+            dead_letter_dir: PathBuf::from("dead-letters"),
+            metrics_address: SocketAddr::from(([0, 0, 0, 0], 9100)),
+        }
+    }
+}
+
+/// Sampling of the stashes for fields not covered by the stash types, enabled by the section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemaDriftConfig {
+    /// Inspect one stash out of this many
+    pub sample_every: NonZeroU32,
+    /// Seconds between two reports of the fields seen so far
+    pub report_interval: u64,
+}
+
+impl Default for SchemaDriftConfig {
+    fn default() -> Self {
+        Self {
+            sample_every: NonZeroU32::new(100).unwrap(),
+            report_interval: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    clickhouse: Option<ClickhouseConfig>,
+    redis: Option<RedisConfig>,
+    oauth: Option<OAuthConfig>,
+    pub token_cache: TokenCacheConfig,
+    pub crawler: CrawlerConfig,
+    pub sinks: SinksConfig,
+    pub schema_drift: Option<SchemaDriftConfig>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ValueKind {
+    String,
+    Integer,
+    List,
+}
+
+/// Environment variable overriding a key of the configuration file
+struct EnvOverride {
+    var: &'static str,
+    section: &'static str,
+    key: &'static str,
+    kind: ValueKind,
+}
+
+const fn env_override(
+    var: &'static str,
+    section: &'static str,
+    key: &'static str,
+    kind: ValueKind,
+) -> EnvOverride {
+    EnvOverride {
+        var,
+        section,
+        key,
+        kind,
+    }
+}
+
+const ENV_OVERRIDES: &[EnvOverride] = &[
+    env_override("CLICKHOUSE_URL", "clickhouse", "url", ValueKind::String),
+    env_override("CLICKHOUSE_USER", "clickhouse", "user", ValueKind::String),
+    env_override(
+        "CLICKHOUSE_PASSWORD",
+        "clickhouse",
+        "password",
+        ValueKind::String,
+    ),
+    env_override(
+        "CLICKHOUSE_DATABASE",
+        "clickhouse",
+        "database",
+        ValueKind::String,
+    ),
+    env_override("REDIS_URL", "redis", "url", ValueKind::String),
+    env_override("CLIENT_ID", "oauth", "client_id", ValueKind::String),
+    env_override("CLIENT_SECRET", "oauth", "client_secret", ValueKind::String),
+    env_override("OAUTH_TOKEN_URL", "oauth", "token_url", ValueKind::String),
+    env_override("TOKEN_CACHE", "token_cache", "backend", ValueKind::String),
+    env_override("TOKEN_CACHE_PATH", "token_cache", "path", ValueKind::String),
+    env_override("TOKEN_CACHE_KEY", "token_cache", "key", ValueKind::String),
+    env_override("REALMS", "crawler", "realms", ValueKind::List),
+    env_override("API_URL", "crawler", "api_url", ValueKind::String),
+    env_override("QUEUE_DEPTH", "crawler", "queue_depth", ValueKind::Integer),
+    env_override(
+        "MAX_IN_FLIGHT",
+        "crawler",
+        "max_in_flight",
+        ValueKind::Integer,
+    ),
+    env_override(
+        "STALL_TIMEOUT",
+        "crawler",
+        "stall_timeout",
+        ValueKind::Integer,
+    ),
+    env_override(
+        "RATE_LIMIT_STORE",
+        "crawler",
+        "rate_limit_store",
+        ValueKind::String,
+    ),
+    env_override(
+        "RATE_LIMIT_PACING",
+        "crawler",
+        "rate_limit_pacing",
+        ValueKind::String,
+    ),
+    env_override(
+        "MAX_ATTEMPTS",
+        "crawler",
+        "max_attempts",
+        ValueKind::Integer,
+    ),
+    env_override(
+        "SNAPSHOT_CAPACITY",
+        "crawler",
+        "snapshot_capacity",
+        ValueKind::Integer,
+    ),
+    env_override("STATS_FILE", "crawler", "stats_file", ValueKind::String),
+    env_override(
+        "CURRENCIES_FILE",
+        "crawler",
+        "currencies_file",
+        ValueKind::String,
+    ),
+    env_override("ARCHIVE_DIR", "sinks", "archive_dir", ValueKind::String),
+    env_override(
+        "DEAD_LETTER_DIR",
+        "sinks",
+        "dead_letter_dir",
+        ValueKind::String,
+    ),
+    env_override(
+        "METRICS_ADDRESS",
+        "sinks",
+        "metrics_address",
+        ValueKind::String,
+    ),
+    env_override(
+        "SCHEMA_DRIFT_SAMPLE_EVERY",
+        "schema_drift",
+        "sample_every",
+        ValueKind::Integer,
+    ),
+    env_override(
+        "SCHEMA_DRIFT_REPORT_INTERVAL",
+        "schema_drift",
+        "report_interval",
+        ValueKind::Integer,
+    ),
+];
+
+impl Config {
+    /// Loads the configuration file, then applies the environment overrides.
+    ///
+    /// Without an explicit path, the file is taken from `PASHE_CONFIG` and falls back to an
+    /// optional `pashe.toml` in the working directory.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match std::env::var_os(CONFIG_PATH_ENV) {
+                Some(path) => (PathBuf::from(path), true),
+                None => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+            },
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => None,
+            Err(source) => return Err(ConfigError::Read { path, source }),
+        };
+
+        Self::from_sources(contents.as_deref(), |var| std::env::var(var).ok())
+    }
+
+    /// Builds the configuration from the contents of a configuration file and an environment
+    pub fn from_sources(
+        contents: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut table = match contents {
+            Some(contents) => contents.parse::<toml::Table>()?,
+            None => toml::Table::new(),
+        };
+
+        for env_override in ENV_OVERRIDES {
+            let Some(value) = env(env_override.var) else {
+                continue;
+            };
+            let value = env_value(env_override, &value)?;
+
+            let section = table
+                .entry(env_override.section)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .ok_or(ConfigError::NotASection(env_override.section))?;
+            section.insert(env_override.key.to_string(), value);
+        }
+
+        if let Some(section) = table.keys().find(|section| {
+            ![
+                "clickhouse",
+                "redis",
+                "oauth",
+                "token_cache",
+                "crawler",
+                "sinks",
+                "schema_drift",
+            ]
+            .contains(&section.as_str())
+        }) {
+            return Err(ConfigError::UnknownSection(section.clone()));
+        }
+
+        let config = Self {
+            clickhouse: section(&mut table, "clickhouse")?,
+            redis: section(&mut table, "redis")?,
+            oauth: section(&mut table, "oauth")?,
+            token_cache: section(&mut table, "token_cache")?.unwrap_or_default(),
+            crawler: section(&mut table, "crawler")?.unwrap_or_default(),
+            sinks: section(&mut table, "sinks")?.unwrap_or_default(),
+            schema_drift: section(&mut table, "schema_drift")?,
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn clickhouse(&self) -> Result<&ClickhouseConfig, ConfigError> {
+        self.clickhouse
+            .as_ref()
+            .ok_or_else(|| missing_section("clickhouse"))
+    }
+
+    pub fn redis(&self) -> Result<&RedisConfig, ConfigError> {
+        self.redis.as_ref().ok_or_else(|| missing_section("redis"))
+    }
+
+    pub fn oauth(&self) -> Result<&OAuthConfig, ConfigError> {
+        self.oauth.as_ref().ok_or_else(|| missing_section("oauth"))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(clickhouse) = &self.clickhouse {
+            validate_url("clickhouse.url", &clickhouse.url, &["http", "https"])?;
+        }
+        if let Some(redis) = &self.redis {
+            validate_url("redis.url", &redis.url, &["redis", "rediss"])?;
+        }
+        if let TokenCacheConfig::EncryptedFile { key, .. } = &self.token_cache
+            && (key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(ConfigError::Invalid {
+                key: "token_cache.key",
+                reason: "expected 64 hexadecimal characters (a 256-bit key)".to_string(),
+            });
+        }
+        if let Some(schema_drift) = &self.schema_drift
+            && schema_drift.report_interval == 0
+        {
+            return Err(ConfigError::Invalid {
+                key: "schema_drift.report_interval",
+                reason: "expected at least one second".to_string(),
+            });
+        }
+        if self.crawler.realms.is_empty() {
+            return Err(ConfigError::Invalid {
+                key: "crawler.realms",
+                reason: "at least one realm is required".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts an environment variable to the TOML value of the key it overrides
+fn env_value(env_override: &EnvOverride, value: &str) -> Result<toml::Value, ConfigError> {
+    match env_override.kind {
+        ValueKind::String => Ok(toml::Value::String(value.to_string())),
+        ValueKind::Integer => {
+            value
+                .trim()
+                .parse()
+                .map(toml::Value::Integer)
+                .map_err(|e| ConfigError::Env {
+                    var: env_override.var,
+                    reason: format!("expected an integer ({e})"),
+                })
+        }
+        ValueKind::List => Ok(toml::Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        )),
+    }
+}
+
+/// Deserializes a section of the configuration, if present
+fn section<T: DeserializeOwned>(
+    table: &mut toml::Table,
+    name: &'static str,
+) -> Result<Option<T>, ConfigError> {
+    table
+        .remove(name)
+        .map(|value| {
+            value
+                .try_into()
+                .map_err(|e: toml::de::Error| ConfigError::Section {
+                    section: name,
+                    hint: env_hint(name),
+                    // Keep the offending key, which is on its own line
+                    reason: e.to_string().trim().replace('\n', " "),
+                })
+        })
+        .transpose()
+}
+
+fn missing_section(name: &'static str) -> ConfigError {
+    ConfigError::MissingSection {
+        section: name,
+        hint: env_hint(name),
+    }
+}
+
+/// Lists the environment variables that can set the keys of a section
+fn env_hint(section: &str) -> String {
+    ENV_OVERRIDES
+        .iter()
+        .filter(|env_override| env_override.section == section)
+        .map(|env_override| env_override.var)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn validate_url(key: &'static str, value: &str, schemes: &[&str]) -> Result<(), ConfigError> {
+    let url = url::Url::parse(value).map_err(|e| ConfigError::Invalid {
+        key,
+        reason: format!("`{value}` is not a valid URL ({e})"),
+    })?;
+
+    if !schemes.contains(&url.scheme()) {
+        return Err(ConfigError::Invalid {
+            key,
+            reason: format!(
+                "unsupported scheme `{}`, expected one of {}",
+                url.scheme(),
+                schemes.join(", ")
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(contents: &str, env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect();
+        Config::from_sources(Some(contents), |var| env.get(var).cloned())
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let contents = r#"
+            [clickhouse]
+            url = "http://localhost:8123"
+            user = "pashe"
+            password = "file"
+            database = "pashe"
+
+            [crawler]
+            queue_depth = 16
+            max_in_flight = 2
+        "#;
+
+        let config = config(
+            contents,
+            &[("CLICKHOUSE_PASSWORD", "env"), ("QUEUE_DEPTH", "32")],
+        )
+        .unwrap();
+
+        let clickhouse = config.clickhouse().unwrap();
+        assert_eq!(clickhouse.password, "env");
+        assert_eq!(clickhouse.user, "pashe");
+        assert_eq!(config.crawler.queue_depth.get(), 32);
+        assert_eq!(config.crawler.max_in_flight.get(), 2);
+        // Keys set nowhere keep their defaults
+        assert_eq!(config.crawler.stall_timeout, 300);
+    }
+
+    #[test]
+    fn coerces_environment_values_to_the_type_of_their_key() {
+        let config = config(
+            "",
+            &[
+                ("STALL_TIMEOUT", " 60 "),
+                ("CLIENT_ID", "1234"),
+                ("CLIENT_SECRET", "secret"),
+                ("REALMS", "pc, xbox,"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.crawler.stall_timeout, 60);
+        // Read as a string even though it looks like an integer
+        assert_eq!(config.oauth().unwrap().client_id, "1234");
+        assert_eq!(config.crawler.realms, ["pc", "xbox"]);
+
+        let error = self::config("", &[("QUEUE_DEPTH", "many")]).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                ConfigError::Env {
+                    var: "QUEUE_DEPTH",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_sections() {
+        let error = config("[crawler]\nqueue_dept = 4", &[]).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                ConfigError::Section {
+                    section: "crawler",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+        assert!(error.to_string().contains("queue_dept"), "{error}");
+
+        let error = config("[crawlers]\nqueue_depth = 4", &[]).unwrap_err();
+        assert!(
+            matches!(&error, ConfigError::UnknownSection(section) if section == "crawlers"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn reports_missing_sections_with_their_environment_variables() {
+        let config = config("", &[]).unwrap();
+
+        let error = config.clickhouse().unwrap_err();
+        assert!(
+            matches!(
+                error,
+                ConfigError::MissingSection {
+                    section: "clickhouse",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+        assert!(error.to_string().contains("CLICKHOUSE_URL"), "{error}");
+        assert!(config.redis().is_err());
+        assert!(config.oauth().is_err());
+    }
+
+    #[test]
+    fn validates_values() {
+        let error = config("[redis]\nurl = \"http://localhost\"", &[]).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                ConfigError::Invalid {
+                    key: "redis.url",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+
+        let error = config("", &[("REALMS", " , ")]).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                ConfigError::Invalid {
+                    key: "crawler.realms",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -13,6 +14,10 @@ pub struct Cli {
     )]
     pub directory: String,
 
+    /// Configuration file, defaults to `pashe.toml` when present
+    #[arg(long, value_name = "FILE", env = "PASHE_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
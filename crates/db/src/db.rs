@@ -9,17 +9,13 @@ pub struct DatabaseConfig {
 }
 
 impl DatabaseConfig {
-    pub fn from_env() -> Result<Self> {
-        Ok(Self {
-            url: std::env::var("CLICKHOUSE_URL")
-                .expect("Missing the CLICKHOUSE_URL environment variable."),
-            user: std::env::var("CLICKHOUSE_USER")
-                .expect("Missing the CLICKHOUSE_USER environment variable."),
-            password: std::env::var("CLICKHOUSE_PASSWORD")
-                .expect("Missing the CLICKHOUSE_PASSWORD environment variable."),
-            database: std::env::var("CLICKHOUSE_DATABASE")
-                .expect("Missing the CLICKHOUSE_DATABASE environment variable."),
-        })
+    pub fn from_config(config: &pashe_config::ClickhouseConfig) -> Self {
+        Self {
+            url: config.url.clone(),
+            user: config.user.clone(),
+            password: config.password.clone(),
+            database: config.database.clone(),
+        }
     }
 
     pub fn new(url: String, user: String, password: String, database: String) -> Self {
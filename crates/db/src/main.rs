@@ -23,7 +23,8 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
-    let client = db::DatabaseConfig::from_env()?.create_client();
+    let config = pashe_config::Config::load(cli.config.as_deref())?;
+    let client = db::DatabaseConfig::from_config(config.clickhouse()?).create_client();
 
     match &cli.command {
         Commands::Migration(migration) => match &migration.command {
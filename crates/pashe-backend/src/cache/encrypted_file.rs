@@ -0,0 +1,176 @@
+use super::{TokenCache, token_ttl};
+use crate::poe::authorization::AccessToken;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of the nonce stored in front of the ciphertext
+const NONCE_SIZE: usize = 12;
+
+#[derive(Serialize, Deserialize)]
+struct CachedToken {
+    secret: String,
+    /// Unix timestamp after which the token must not be used
+    expires_at: u64,
+}
+
+/// Keeps the token in a local file encrypted with ChaCha20-Poly1305
+pub struct EncryptedFileTokenCache {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for EncryptedFileTokenCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedFileTokenCache")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedFileTokenCache {
+    /// `key` is the hexadecimal encoding of a 256-bit key
+    pub fn new(path: PathBuf, key: &str) -> Result<Self> {
+        let key = hex::decode(key).context("The token cache key must be hexadecimal")?;
+        anyhow::ensure!(key.len() == 32, "The token cache key must be 256 bits long");
+
+        Ok(Self {
+            path,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[async_trait]
+impl TokenCache for EncryptedFileTokenCache {
+    async fn get(&self) -> Result<Option<String>> {
+        let data = match tokio::fs::read(&self.path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        anyhow::ensure!(data.len() > NONCE_SIZE, "Truncated token cache file");
+
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                anyhow::anyhow!("Failed to decrypt the token cache, was the key changed?")
+            })?;
+        let token: CachedToken = serde_json::from_slice(&plaintext)?;
+
+        Ok((token.expires_at > unix_now()).then_some(token.secret))
+    }
+
+    async fn set(&self, token: &AccessToken) -> Result<()> {
+        self.write(&CachedToken {
+            secret: token.secret.clone(),
+            expires_at: unix_now() + token_ttl(token).as_secs(),
+        })
+        .await
+    }
+
+    async fn invalidate(&self) -> Result<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl EncryptedFileTokenCache {
+    async fn write(&self, token: &CachedToken) -> Result<()> {
+        let plaintext = serde_json::to_vec(token)?;
+
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt the token cache"))?;
+
+        // Write to a temporary file first, so that a crash never leaves a truncated cache
+        let temporary_path = self.path.with_extension("tmp");
+        tokio::fs::write(&temporary_path, [nonce.as_slice(), &ciphertext].concat())
+            .await
+            .with_context(|| format!("Failed to write {}", temporary_path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&temporary_path, std::fs::Permissions::from_mode(0o600))
+                .await?;
+        }
+        tokio::fs::rename(&temporary_path, &self.path).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pashe-token-cache-{name}-{}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn reloads_the_encrypted_token() {
+        let path = cache_path("round-trip");
+        let cache = EncryptedFileTokenCache::new(path.clone(), KEY).unwrap();
+        assert_eq!(cache.get().await.unwrap(), None);
+
+        cache
+            .set(&AccessToken {
+                secret: "secret".to_string(),
+                expires_in: Some(Duration::from_secs(3600)),
+            })
+            .await
+            .unwrap();
+
+        // The secret isn't written in clear
+        let data = std::fs::read(&path).unwrap();
+        assert!(!data.windows(6).any(|window| window == b"secret"));
+
+        let reloaded = EncryptedFileTokenCache::new(path.clone(), KEY).unwrap();
+        assert_eq!(reloaded.get().await.unwrap().as_deref(), Some("secret"));
+
+        let other_key = KEY.replace("00", "ff");
+        let other = EncryptedFileTokenCache::new(path.clone(), &other_key).unwrap();
+        assert!(other.get().await.is_err());
+
+        reloaded.invalidate().await.unwrap();
+        assert_eq!(reloaded.get().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn ignores_expired_tokens() {
+        let path = cache_path("expiry");
+        let cache = EncryptedFileTokenCache::new(path.clone(), KEY).unwrap();
+
+        cache
+            .write(&CachedToken {
+                secret: "secret".to_string(),
+                expires_at: unix_now() - 1,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(cache.get().await.unwrap(), None);
+        cache.invalidate().await.unwrap();
+    }
+}
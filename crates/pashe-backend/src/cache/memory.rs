@@ -0,0 +1,34 @@
+use super::{TokenCache, token_ttl};
+use crate::poe::authorization::AccessToken;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Keeps the token in memory, for local runs and tests
+#[derive(Debug, Default)]
+pub struct MemoryTokenCache {
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+#[async_trait]
+impl TokenCache for MemoryTokenCache {
+    async fn get(&self) -> Result<Option<String>> {
+        let token = self.token.lock().unwrap();
+        Ok(token
+            .as_ref()
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(secret, _)| secret.clone()))
+    }
+
+    async fn set(&self, token: &AccessToken) -> Result<()> {
+        *self.token.lock().unwrap() =
+            Some((token.secret.clone(), Instant::now() + token_ttl(token)));
+        Ok(())
+    }
+
+    async fn invalidate(&self) -> Result<()> {
+        *self.token.lock().unwrap() = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+mod encrypted_file;
+mod memory;
+mod redis;
+
+pub use encrypted_file::EncryptedFileTokenCache;
+pub use memory::MemoryTokenCache;
+pub use redis::RedisTokenCache;
+
+use crate::poe::authorization::AccessToken;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use pashe_config::{Config, RateLimitStoreConfig, RedisConfig, TokenCacheConfig};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Validity period assumed for tokens issued without an expiry
+const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(27 * 24 * 60 * 60);
+/// Tokens are evicted this long before they expire, so that a cached token is never stale
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Storage for the OAuth access token, so that it survives restarts
+#[async_trait]
+pub trait TokenCache: Debug + Send + Sync {
+    /// Returns the cached token, unless it expired
+    async fn get(&self) -> Result<Option<String>>;
+
+    /// Caches the token for as long as the server said it would be valid
+    async fn set(&self, token: &AccessToken) -> Result<()>;
+
+    /// Removes a token that the API rejected
+    async fn invalidate(&self) -> Result<()>;
+}
+
+/// How long a token can be cached for
+fn token_ttl(token: &AccessToken) -> Duration {
+    token
+        .expires_in
+        .map(|expires_in| expires_in.saturating_sub(TOKEN_EXPIRY_MARGIN))
+        .unwrap_or(DEFAULT_TOKEN_TTL)
+        .max(Duration::from_secs(1))
+}
+
+/// Whether the token cache or the rate limit store is kept in Redis
+pub fn uses_redis(config: &Config) -> bool {
+    matches!(config.token_cache, TokenCacheConfig::Redis)
+        || matches!(config.crawler.rate_limit_store, RateLimitStoreConfig::Redis)
+}
+
+/// Opens the connection shared by everything stored in Redis, it reconnects on its own
+pub async fn connect_redis(config: &RedisConfig) -> Result<::redis::aio::ConnectionManager> {
+    let client = ::redis::Client::open(config.url.as_str())?;
+    ::redis::aio::ConnectionManager::new(client)
+        .await
+        .with_context(|| format!("Failed to connect to Redis at {}", config.url))
+}
+
+/// Creates the token cache selected by the configuration
+pub fn from_config(
+    config: &Config,
+    redis: Option<::redis::aio::ConnectionManager>,
+) -> Result<Arc<dyn TokenCache>> {
+    Ok(match &config.token_cache {
+        TokenCacheConfig::Redis => {
+            // Surfaces the missing configuration rather than a missing connection
+            config.redis()?;
+            let redis = redis.context("The Redis token cache requires a Redis connection")?;
+            Arc::new(RedisTokenCache::new(redis))
+        }
+        TokenCacheConfig::EncryptedFile { path, key } => {
+            Arc::new(EncryptedFileTokenCache::new(path.clone(), key)?)
+        }
+        TokenCacheConfig::Memory => Arc::new(MemoryTokenCache::default()),
+    })
+}
@@ -0,0 +1,47 @@
+use super::{TokenCache, token_ttl};
+use crate::poe::authorization::AccessToken;
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+
+const TOKEN_KEY: &str = "access_token";
+
+/// Caches the token in Redis, through the shared connection
+#[derive(Clone)]
+pub struct RedisTokenCache {
+    connection: ConnectionManager,
+}
+
+impl std::fmt::Debug for RedisTokenCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisTokenCache").finish_non_exhaustive()
+    }
+}
+
+impl RedisTokenCache {
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self { connection }
+    }
+}
+
+#[async_trait]
+impl TokenCache for RedisTokenCache {
+    async fn get(&self) -> Result<Option<String>> {
+        Ok(self.connection.clone().get(TOKEN_KEY).await?)
+    }
+
+    async fn set(&self, token: &AccessToken) -> Result<()> {
+        let _: () = self
+            .connection
+            .clone()
+            .set_ex(TOKEN_KEY, &token.secret, token_ttl(token).as_secs())
+            .await?;
+        Ok(())
+    }
+
+    async fn invalidate(&self) -> Result<()> {
+        let _: () = self.connection.clone().del(TOKEN_KEY).await?;
+        Ok(())
+    }
+}
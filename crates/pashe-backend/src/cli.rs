@@ -0,0 +1,90 @@
+use crate::poe::realm::Realm;
+use clap::{Parser, Subcommand};
+use pashe_config::Config;
+use std::{net::SocketAddr, num::NonZeroUsize, path::PathBuf};
+
+/// Command line arguments, the crawler settings override those of the configuration file
+#[derive(Parser, Clone)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    /// Configuration file, defaults to `pashe.toml` when present
+    #[arg(long, value_name = "FILE", env = "PASHE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Realms to crawl, each one with its own change ID stream
+    #[arg(long, value_name = "REALM", value_delimiter = ',')]
+    pub realms: Vec<Realm>,
+
+    /// Archive every fetched page to this directory, keyed by realm and change ID
+    #[arg(long, value_name = "DIR")]
+    pub archive_dir: Option<PathBuf>,
+
+    /// Number of decoded pages that can wait for the processor before the crawler slows down
+    #[arg(long, value_name = "PAGES")]
+    pub queue_depth: Option<NonZeroUsize>,
+
+    /// Maximum number of pages being fetched concurrently
+    #[arg(long, value_name = "PAGES")]
+    pub max_in_flight: Option<NonZeroUsize>,
+
+    /// Address serving the Prometheus metrics and the health endpoints
+    #[arg(long, value_name = "ADDRESS")]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// Seconds without an ingested page after which the backend reports itself unhealthy
+    #[arg(long, value_name = "SECONDS")]
+    pub stall_timeout: Option<u64>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+impl Cli {
+    /// Applies the arguments given on the command line on top of the configuration
+    pub fn apply_overrides(&self, config: &mut Config) {
+        if !self.realms.is_empty() {
+            config.crawler.realms = self.realms.iter().map(Realm::to_string).collect();
+        }
+        if let Some(archive_dir) = &self.archive_dir {
+            config.sinks.archive_dir = Some(archive_dir.clone());
+        }
+        if let Some(queue_depth) = self.queue_depth {
+            config.crawler.queue_depth = queue_depth;
+        }
+        if let Some(max_in_flight) = self.max_in_flight {
+            config.crawler.max_in_flight = max_in_flight;
+        }
+        if let Some(metrics_address) = self.metrics_address {
+            config.sinks.metrics_address = metrics_address;
+        }
+        if let Some(stall_timeout) = self.stall_timeout {
+            config.crawler.stall_timeout = stall_timeout;
+        }
+    }
+}
+
+#[derive(Subcommand, Clone, Default)]
+pub enum Commands {
+    /// Crawl the public stash API (default)
+    #[default]
+    Crawl,
+    /// Replay archived pages into the database without any network access
+    #[command(arg_required_else_help = true)]
+    Replay {
+        /// Directory containing the archived pages
+        directory: PathBuf,
+
+        /// Realm the archived pages were crawled from
+        #[arg(long, default_value = "pc")]
+        realm: Realm,
+    },
+    /// Retry the pages that failed to parse and were dead-lettered, e.g. after fixing the stash
+    /// types
+    RetryDeadLetters {
+        /// Realm the pages were crawled from
+        #[arg(long, default_value = "pc")]
+        realm: Realm,
+    },
+    /// Check the health of a running backend, exiting with an error if it is unhealthy
+    Healthcheck,
+}
@@ -0,0 +1,496 @@
+use anyhow::{Context, Result};
+use http::header::ACCEPT_ENCODING;
+use human_repr::HumanCount;
+use oauth2::reqwest;
+use reqwest::header::{ACCEPT, HeaderValue, USER_AGENT};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{Semaphore, mpsc},
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::cache::{self, TokenCache};
+use crate::db;
+use crate::health::Health;
+use crate::poe;
+use crate::poe::authorization::AuthorizationMiddleware;
+use crate::poe::currency::CurrencyCatalogue;
+use crate::poe::public_stash_worker::{PublicStashWorker, StashPage};
+use crate::poe::rate_limit::{MemoryStore, RateLimitMiddleware, RateLimitStore, RedisStore};
+use crate::poe::realm::Realm;
+use crate::poe::retry::RetryMiddleware;
+use crate::poe::schema_drift::SchemaDrift;
+use crate::poe::stats::StatTemplates;
+use crate::telemetry;
+use pashe_config::{Config, RateLimitStoreConfig};
+use redis::aio::ConnectionManager;
+
+async fn get_access_token(
+    http_client: &reqwest::Client,
+    config: &Config,
+    token_cache: &dyn TokenCache,
+) -> Result<String> {
+    match token_cache.get().await {
+        Ok(Some(token)) => {
+            debug!("Using cached access token");
+            return Ok(token);
+        }
+        Ok(None) => debug!("No cached access token, fetching a new one"),
+        Err(e) => warn!(
+            "Failed to retrieve cached access token, fetching a new one: {:#}",
+            e
+        ),
+    }
+
+    let access_token = poe::authorization::fetch_access_token(http_client, config.oauth()?).await?;
+    token_cache.set(&access_token).await?;
+    debug!("New access token cached successfully");
+
+    Ok(access_token.secret)
+}
+
+/// Resumes from the last committed checkpoint, falling back to poe.ninja on a fresh database
+async fn get_initial_change_id(db: &db::Client, realm: Realm) -> Result<String> {
+    if let Some(change_id) = db.last_checkpoint(&realm.to_string()).await? {
+        debug!("Resuming {} from checkpoint", realm);
+        return Ok(change_id);
+    }
+
+    let Some(ninja_stats_url) = realm.ninja_stats_url() else {
+        warn!(
+            "No checkpoint found and no poe.ninja source for {}, starting from the beginning of its stream",
+            realm
+        );
+        return Ok(String::new());
+    };
+
+    debug!("No checkpoint found, fetching initial next_change_id from poe.ninja");
+    let ninja = reqwest::get(ninja_stats_url)
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+
+    let next_change_id = ninja["next_change_id"]
+        .as_str()
+        .ok_or(anyhow::anyhow!(
+            "Failed to get next_change_id from poe.ninja response"
+        ))?
+        .to_string();
+
+    Ok(next_change_id)
+}
+
+/// Crawls the realms until shutdown, each one from its last checkpoint
+pub async fn crawl(
+    db: db::Client,
+    redis: Option<ConnectionManager>,
+    config: Arc<Config>,
+    realms: &[Realm],
+    health: Arc<Health>,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    const PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
+    const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
+    const PACKAGE_AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        USER_AGENT,
+        format!("OAuth {PACKAGE_NAME}/{PACKAGE_VERSION} (contact: {PACKAGE_AUTHOR})").parse()?,
+    );
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+    let http_client = reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .default_headers(headers.clone())
+        .build()?;
+
+    let item_data = ItemData::load(&config)?;
+
+    let token_cache = cache::from_config(&config, redis.clone())?;
+    let access_token = get_access_token(&http_client, &config, token_cache.as_ref()).await?;
+    health.set_token_valid(true);
+
+    // The token is refreshed through this client whenever the API rejects it
+    let authorization = Arc::new(AuthorizationMiddleware::new(
+        http_client,
+        Arc::clone(&config),
+        token_cache,
+        Arc::clone(&health),
+        access_token,
+    ));
+
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+
+    let http_client = reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .no_gzip()
+        .default_headers(headers.clone())
+        .build()?;
+
+    if let Some(archive_dir) = &config.sinks.archive_dir {
+        info!("Archiving fetched pages to {}", archive_dir.display());
+    }
+
+    // Realms share the rate limit budget of the OAuth client
+    let rate_limit_store: Arc<dyn RateLimitStore> = match config.crawler.rate_limit_store {
+        RateLimitStoreConfig::Memory => Arc::new(MemoryStore::default()),
+        RateLimitStoreConfig::Redis => Arc::new(RedisStore::new(
+            redis.context("The Redis rate limit store requires a [redis] configuration")?,
+            &config.oauth()?.client_id,
+        )),
+    };
+
+    // Retries go through authorization and rate limiting again
+    let http_client = reqwest_middleware::ClientBuilder::new(http_client)
+        .with(RetryMiddleware::new(
+            shutdown_token.clone(),
+            config.crawler.max_attempts.get(),
+        ))
+        .with_arc(authorization)
+        .with(RateLimitMiddleware::new(
+            shutdown_token.clone(),
+            rate_limit_store,
+            config.crawler.rate_limit_pacing,
+        ))
+        .build();
+
+    // Each realm has its own change ID stream and checkpoint
+    let mut crawlers = JoinSet::new();
+    for &realm in realms {
+        crawlers.spawn(crawl_realm(
+            realm,
+            db.clone(),
+            http_client.clone(),
+            Arc::clone(&config),
+            Arc::clone(&health),
+            item_data.clone(),
+            shutdown_token.clone(),
+        ));
+    }
+
+    // A failing realm stops the others, the process is restarted from the checkpoints
+    let mut failure = None;
+    while let Some(result) = crawlers.join_next().await {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Crawler failed: {:#}", e);
+                shutdown_token.cancel();
+                failure.get_or_insert(e);
+            }
+            Err(e) => error!("Crawler task failed: {}", e),
+        }
+    }
+
+    failure.map_or(Ok(()), Err)
+}
+
+async fn crawl_realm(
+    realm: Realm,
+    db: db::Client,
+    http_client: reqwest_middleware::ClientWithMiddleware,
+    config: Arc<Config>,
+    health: Arc<Health>,
+    item_data: ItemData,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    let next_change_id = get_initial_change_id(&db, realm).await?;
+
+    info!(
+        "Starting {} crawler at next_change_id: {}",
+        realm, next_change_id
+    );
+
+    let stash_crawler = Arc::new(
+        PublicStashWorker::new(shutdown_token.clone(), realm, health)
+            .with_api_url(config.crawler.api_url.clone())
+            .with_archive_dir(
+                config
+                    .sinks
+                    .archive_dir
+                    .as_ref()
+                    .map(|archive_dir| archive_dir.join(realm.to_string())),
+            )
+            .with_dead_letter_dir(config.sinks.dead_letter_dir.join(realm.to_string()))
+            .with_schema_drift(start_schema_drift(&config, realm, &shutdown_token))
+            .with_stat_templates(item_data.stat_templates)
+            .with_currencies(item_data.currencies)
+            .with_snapshot_capacity(config.crawler.snapshot_capacity),
+    );
+
+    // Set up channels for concurrent crawling. Only one change ID is pending at any time, while
+    // decoded pages queue up for the processor: once the queue is full, fetch tasks wait for room
+    // and hold on to their permit, which in turn slows down the crawler.
+    let (next_change_id_tx, mut next_change_id_rx) = mpsc::channel::<(u64, String)>(1);
+    let (stash_changes_tx, stash_changes_rx) =
+        mpsc::channel::<StashPage>(config.crawler.queue_depth.get());
+    let fetch_permits = Arc::new(Semaphore::new(config.crawler.max_in_flight.get()));
+    // A change ID that can't be fetched breaks the chain, the first failure stops the realm
+    let (fetch_failure_tx, mut fetch_failure_rx) = mpsc::channel::<anyhow::Error>(1);
+    let mut fetch_failure = None;
+
+    // Start the stash processor task
+    let processor_self = Arc::clone(&stash_crawler);
+    let processor_handle =
+        tokio::spawn(async move { processor_self.process_stash(stash_changes_rx, db).await });
+
+    // Send the initial change ID to start the process
+    next_change_id_tx.send((0, next_change_id)).await?;
+
+    // Main crawling loop
+    loop {
+        tokio::select! {
+            // Check for shutdown
+            _ = shutdown_token.cancelled() => {
+                debug!("Shutting down crawler");
+                break;
+            }
+
+            Some(e) = fetch_failure_rx.recv() => {
+                fetch_failure = Some(e);
+                break;
+            }
+
+            // Process new change IDs
+            Some((sequence, change_id)) = next_change_id_rx.recv() => {
+                let permit = match Arc::clone(&fetch_permits).try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        debug!(
+                            "Crawler throttled: {} pages in flight, processing queue {}/{}",
+                            config.crawler.max_in_flight,
+                            stash_changes_tx.max_capacity() - stash_changes_tx.capacity(),
+                            stash_changes_tx.max_capacity(),
+                        );
+                        tokio::select! {
+                            permit = Arc::clone(&fetch_permits).acquire_owned() => permit?,
+                            _ = shutdown_token.cancelled() => {
+                                debug!("Shutting down crawler");
+                                break;
+                            }
+                        }
+                    }
+                };
+
+                let client_clone = Arc::new(http_client.clone());
+                let next_change_id_tx_clone = next_change_id_tx.clone();
+                let stash_changes_tx_clone = stash_changes_tx.clone();
+                let stash_crawler_clone = Arc::clone(&stash_crawler);
+                let fetch_failure_tx = fetch_failure_tx.clone();
+                let pages_in_flight =
+                    metrics::gauge!(telemetry::PAGES_IN_FLIGHT, "realm" => realm.to_string());
+                pages_in_flight.increment(1);
+
+                // Spawn a new task for each next change ID
+                tokio::spawn(async move {
+                    if let Err(e) = stash_crawler_clone.fetch_stash(
+                        client_clone,
+                        sequence,
+                        change_id,
+                        next_change_id_tx_clone,
+                        stash_changes_tx_clone,
+                    ).await {
+                        // Only the first failure is kept, the realm is stopping anyway
+                        let _ = fetch_failure_tx.try_send(e);
+                    }
+                    drop(permit);
+                    pages_in_flight.decrement(1);
+                });
+            }
+
+            // Break if the channel is closed and no more IDs are coming
+            else => break,
+        }
+    }
+
+    // Stop the fetches still in flight, their pages are fetched again from the checkpoint
+    if fetch_failure.is_some() {
+        shutdown_token.cancel();
+    }
+
+    // Clean shutdown: drop the senders to signal processors to stop
+    drop(next_change_id_tx);
+    drop(stash_changes_tx);
+
+    // Wait for the processor to finish, it cancels the crawl when it can't go on
+    match processor_handle.await {
+        Ok(result) => result?,
+        Err(e) => error!("Processor task failed: {}", e),
+    }
+
+    if let Some(e) = fetch_failure {
+        return Err(e.context(format!("Failed to crawl {realm}")));
+    }
+
+    shutdown_token.cancelled().await;
+
+    Ok(())
+}
+
+/// Replays archived pages of a realm through the stash processor
+pub async fn replay(
+    db: db::Client,
+    config: &Config,
+    directory: &Path,
+    realm: Realm,
+    health: Arc<Health>,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    let pages = poe::archive::list_pages(directory)?;
+    info!(
+        "Replaying {} archived pages from {}",
+        pages.len().human_count_bare(),
+        directory.display()
+    );
+
+    replay_pages(db, config, pages, realm, health, shutdown_token).await?;
+    Ok(())
+}
+
+/// Retries the dead-lettered pages of a realm that failed to parse, removing those that now go
+/// through
+pub async fn retry_dead_letters(
+    db: db::Client,
+    config: &Config,
+    realm: Realm,
+    health: Arc<Health>,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    let directory = config.sinks.dead_letter_dir.join(realm.to_string());
+    let pages = poe::dead_letter::list_pages(&directory)?;
+    info!(
+        "Retrying {} dead-lettered pages from {}",
+        pages.len().human_count_bare(),
+        directory.display()
+    );
+
+    let page_count = pages.len();
+    // Pages are only removed once inserted, the others are kept for next time
+    let retried = replay_pages(db, config, pages, realm, health, shutdown_token).await?;
+    for change_id in &retried {
+        poe::dead_letter::remove_page(&directory, change_id).await?;
+    }
+    info!(
+        "Retried {} pages, {} still fail",
+        retried.len().human_count_bare(),
+        (page_count - retried.len()).human_count_bare()
+    );
+
+    Ok(())
+}
+
+/// Runs pages through the stash processor, returning the change IDs of those that were inserted
+async fn replay_pages(
+    db: db::Client,
+    config: &Config,
+    pages: Vec<(String, PathBuf)>,
+    realm: Realm,
+    health: Arc<Health>,
+    shutdown_token: CancellationToken,
+) -> Result<Vec<String>> {
+    let schema_drift = start_schema_drift(config, realm, &shutdown_token);
+    let item_data = ItemData::load(config)?;
+    let stash_replayer = Arc::new(
+        PublicStashWorker::new(shutdown_token, realm, health)
+            .with_schema_drift(schema_drift.clone())
+            .with_stat_templates(item_data.stat_templates)
+            .with_currencies(item_data.currencies)
+            .with_snapshot_capacity(config.crawler.snapshot_capacity)
+            .without_checkpoints(),
+    );
+
+    let (stash_changes_tx, stash_changes_rx) =
+        mpsc::channel::<StashPage>(config.crawler.queue_depth.get());
+
+    // Start the stash processor task
+    let processor_self = Arc::clone(&stash_replayer);
+    let processor_handle =
+        tokio::spawn(async move { processor_self.process_stash(stash_changes_rx, db).await });
+
+    let replayed = stash_replayer.replay(pages, stash_changes_tx).await;
+
+    // The sender was consumed by the replay, wait for the processor to drain the queue
+    match processor_handle.await {
+        Ok(Err(e)) => error!("Stash processor failed: {:#}", e),
+        Ok(Ok(())) => {}
+        Err(e) => error!("Processor task failed: {}", e),
+    }
+
+    if let Some(schema_drift) = schema_drift {
+        schema_drift.report();
+    }
+
+    // The processor is done, every page was either inserted or dropped
+    let mut inserted = Vec::new();
+    for (change_id, inserted_rx) in replayed? {
+        if inserted_rx.await.is_ok() {
+            inserted.push(change_id);
+        }
+    }
+
+    Ok(inserted)
+}
+
+/// Reference data the items are parsed against, loaded once and shared by the realms
+#[derive(Clone)]
+struct ItemData {
+    stat_templates: Arc<StatTemplates>,
+    currencies: Arc<CurrencyCatalogue>,
+}
+
+impl ItemData {
+    fn load(config: &Config) -> Result<Self> {
+        let stat_templates = StatTemplates::load(&config.crawler.stats_file)?;
+        info!(
+            "Loaded {} stat templates from {}",
+            stat_templates.len().human_count_bare(),
+            config.crawler.stats_file.display()
+        );
+
+        let currencies = CurrencyCatalogue::load(&config.crawler.currencies_file)?;
+        info!(
+            "Loaded {} currencies from {}",
+            currencies.len().human_count_bare(),
+            config.crawler.currencies_file.display()
+        );
+
+        Ok(Self {
+            stat_templates: Arc::new(stat_templates),
+            currencies: Arc::new(currencies),
+        })
+    }
+}
+
+/// Starts sampling the stashes of a realm for schema drift when configured, reporting it
+/// periodically until shutdown
+fn start_schema_drift(
+    config: &Config,
+    realm: Realm,
+    shutdown_token: &CancellationToken,
+) -> Option<Arc<SchemaDrift>> {
+    let schema_drift_config = config.schema_drift.as_ref()?;
+    let schema_drift = Arc::new(SchemaDrift::new(
+        realm.to_string(),
+        schema_drift_config.sample_every,
+    ));
+
+    tokio::spawn({
+        let schema_drift = Arc::clone(&schema_drift);
+        let report_interval = Duration::from_secs(schema_drift_config.report_interval);
+        let shutdown_token = shutdown_token.clone();
+        async move {
+            schema_drift
+                .report_periodically(report_interval, shutdown_token)
+                .await;
+        }
+    });
+
+    Some(schema_drift)
+}
@@ -1,10 +1,14 @@
 use super::error::Error;
-use super::schema::StatisticsEvent;
+use super::schema::{Checkpoint, ItemListing, StashEvent, StashListing, StatisticsEvent};
 use crate::db::Item;
+use crate::telemetry;
+use clickhouse::Row;
 use human_repr::HumanCount;
-use std::time::Duration;
+use serde::Serialize;
+use std::time::{Duration, Instant};
 use tracing::debug;
 
+#[derive(Clone)]
 pub struct Client {
     client: clickhouse::Client,
 }
@@ -19,6 +23,13 @@ impl Client {
         Self { client }
     }
 
+    /// Checks that the database can be reached
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.client.query("SELECT 1").execute().await?;
+        Ok(())
+    }
+
     #[tracing::instrument(skip_all, level = "trace")]
     pub async fn insert_statistics_event(&self, event: StatisticsEvent) -> Result<(), Error> {
         let mut insert = self.client.insert::<StatisticsEvent>("statistics_events")?;
@@ -29,28 +40,102 @@ impl Client {
     }
 
     #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn insert_items(&self, items: Vec<Item>) -> Result<(), Error> {
+    pub async fn insert_items(&self, items: &[Item], token: &str) -> Result<(), Error> {
+        self.insert_batch("items", items, token).await
+    }
+
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn insert_stash_events(
+        &self,
+        events: &[StashEvent],
+        token: &str,
+    ) -> Result<(), Error> {
+        self.insert_batch("stash_events", events, token).await
+    }
+
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn insert_item_listings(
+        &self,
+        listings: &[ItemListing],
+        token: &str,
+    ) -> Result<(), Error> {
+        self.insert_batch("item_listings", listings, token).await
+    }
+
+    /// Inserts a batch of rows in a single commit. The server drops a batch whose token it has
+    /// already seen in the table, so inserting the same batch again is a no-op
+    async fn insert_batch<T: Row + Serialize>(
+        &self,
+        table: &str,
+        rows: &[T],
+        token: &str,
+    ) -> Result<(), Error> {
+        // Without row or byte thresholds, the rows go out in a single INSERT under the token
         let mut inserter = self
             .client
-            .inserter::<Item>("items")?
+            .inserter::<T>(table)?
             .with_timeouts(Some(Duration::from_secs(5)), Some(Duration::from_secs(20)))
-            .with_max_bytes(50_000_000)
-            .with_max_rows(750_000);
+            .with_option("insert_deduplication_token", token);
 
-        items.iter().try_for_each(|item| inserter.write(item))?;
-        inserter.commit().await?;
+        let start_time = Instant::now();
+        rows.iter().try_for_each(|row| inserter.write(row))?;
         let stats = inserter.end().await?;
+        metrics::histogram!(telemetry::INSERT_DURATION, "table" => table.to_string())
+            .record(start_time.elapsed());
 
         if stats.rows > 0 {
             debug!(
-                "{} items ({}) have been inserted",
+                "{} rows ({}) have been inserted into {}",
                 stats.rows.human_count_bare(),
                 stats.bytes.human_count_bytes(),
+                table,
             )
         } else {
-            debug!("No rows inserted");
+            debug!("No rows inserted into {}", table);
         }
 
         Ok(())
     }
+
+    /// Returns the priced listings currently up in the given stashes of a realm
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn current_stash_listings(
+        &self,
+        realm: &str,
+        stash_ids: &[&str],
+    ) -> Result<Vec<StashListing>, Error> {
+        let listings = self
+            .client
+            .query("SELECT ?fields FROM current_listings FINAL WHERE realm = ? AND stash_id IN ?")
+            .bind(realm)
+            .bind(stash_ids)
+            .fetch_all::<StashListing>()
+            .await?;
+
+        Ok(listings)
+    }
+
+    /// Returns the most recently committed change ID of a realm, if any
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn last_checkpoint(&self, realm: &str) -> Result<Option<String>, Error> {
+        let checkpoint = self
+            .client
+            .query(
+                "SELECT ?fields FROM crawl_checkpoints WHERE realm = ? ORDER BY timestamp DESC LIMIT 1",
+            )
+            .bind(realm)
+            .fetch_optional::<Checkpoint>()
+            .await?;
+
+        Ok(checkpoint.map(|checkpoint| checkpoint.change_id))
+    }
+
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn insert_checkpoint(&self, checkpoint: Checkpoint) -> Result<(), Error> {
+        let mut insert = self.client.insert::<Checkpoint>("crawl_checkpoints")?;
+
+        insert.write(&checkpoint).await?;
+        insert.end().await?;
+        Ok(())
+    }
 }
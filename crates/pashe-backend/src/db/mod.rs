@@ -3,4 +3,7 @@ mod error;
 mod schema;
 
 pub use client::Client;
-pub use schema::{Item, ListingCurrency, StatisticsEvent};
+pub use schema::{
+    Checkpoint, Item, ItemListing, ListingType, PeriodType, SchemaMigration, StashEvent,
+    StashEventKind, StashListing, StatisticsEvent, StatisticsPerPeriod,
+};
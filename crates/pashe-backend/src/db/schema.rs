@@ -2,7 +2,6 @@ use chrono::{DateTime, Utc};
 use clickhouse::Row;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use strum_macros::{Display, EnumString};
 
 /// Schema migration tracking
 #[derive(Debug, Row, Serialize, Deserialize)]
@@ -12,41 +11,31 @@ pub struct SchemaMigration {
     pub applied_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Display, EnumString, Clone)]
-pub enum ListingCurrency {
-    #[strum(serialize = "alch")]
-    AlchemyOrb,
-    #[strum(serialize = "alt")]
-    AlterationOrb,
-    #[strum(serialize = "annul")]
-    AnnulmentOrb,
-    #[strum(serialize = "chance")]
-    ChanceOrb,
-    #[strum(serialize = "chaos")]
-    ChaosOrb,
-    #[strum(serialize = "divine")]
-    DivineOrb,
-    #[strum(serialize = "exalted")]
-    ExaltedOrb,
-    #[strum(serialize = "fusing")]
-    FusingOrb,
-    #[strum(serialize = "mirror")]
-    MirrorOfKalandra,
-    #[strum(serialize = "regal")]
-    RegalOrb,
-    #[strum(serialize = "scour")]
-    ScouringOrb,
-    #[default]
-    #[strum(serialize = "unknown")]
-    Unknown,
+/// How a listing is priced, from the prefix of its note
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i8)]
+pub enum ListingType {
+    /// Rows written before the listing type was recorded, and removals of listings
+    Unknown = 0,
+    /// `~price`, negotiable
+    Price = 1,
+    /// `~b/o`, buyout
+    Buyout = 2,
 }
 
-/// Individual item in a stash
+/// Observation of a listed item, inserted again on every update of its stash.
+///
+/// The `current_listings` table deduplicates observations by item ID, query it with `FINAL` for
+/// the distinct listings currently up.
 #[derive(Debug, Row, Serialize, Deserialize)]
 pub struct Item {
     #[serde(with = "clickhouse::serde::chrono::datetime")]
     pub timestamp: DateTime<Utc>,
+    pub realm: String,
     pub league: String,
+    pub stash_id: String,
+    /// Stable across stash updates, identifies the listing in `current_listings`
+    pub item_id: String,
     pub base: String,
     pub name: String,
     pub links: u8,
@@ -63,9 +52,102 @@ pub struct Item {
     pub tier: u8,
     /// Base type influences
     pub influences: Vec<String>,
+    /// Modifiers as displayed in game, empty when the item has none
+    pub enchant_mods: Vec<String>,
+    pub implicit_mods: Vec<String>,
+    pub explicit_mods: Vec<String>,
+    pub crafted_mods: Vec<String>,
+    pub fractured_mods: Vec<String>,
+    pub veiled_mods: Vec<String>,
+    pub scourge_mods: Vec<String>,
+    pub crucible_mods: Vec<String>,
+    pub utility_mods: Vec<String>,
+    pub rune_mods: Vec<String>,
+    /// Trade site stats of the mods, along with the values of each one
+    pub stat_ids: Vec<String>,
+    pub stat_values: Vec<Vec<f32>>,
+    /// Totals derived from the stats, see [`crate::poe::pseudo::PseudoStats`]
+    pub pseudo_total_life: f32,
+    pub pseudo_total_mana: f32,
+    pub pseudo_total_energy_shield: f32,
+    pub pseudo_total_elemental_resistance: f32,
+    pub pseudo_total_chaos_resistance: f32,
+    pub pseudo_total_strength: f32,
+    pub pseudo_total_dexterity: f32,
+    pub pseudo_total_intelligence: f32,
+    pub pseudo_open_prefixes: u8,
+    pub pseudo_open_suffixes: u8,
     /// Pricing
+    pub listing_type: ListingType,
+    pub price_quantity: f32,
+    pub price_currency: String,
+    /// Whether the currency is in the catalogue, unknown ones are stored as written in the note
+    pub price_currency_known: bool,
+}
+
+/// Kind of change of a listing between two updates of its stash
+#[derive(Debug, Clone, Copy, PartialEq, Serialize_repr, Deserialize_repr)]
+#[repr(i8)]
+pub enum StashEventKind {
+    Added = 1,
+    Removed = 2,
+    Repriced = 3,
+}
+
+/// Listing added to, removed from or repriced in a stash
+#[derive(Debug, Row, Serialize, Deserialize)]
+pub struct StashEvent {
+    #[serde(with = "clickhouse::serde::chrono::datetime")]
+    pub timestamp: DateTime<Utc>,
+    pub realm: String,
+    pub league: String,
+    pub stash_id: String,
+    pub item_id: String,
+    pub kind: StashEventKind,
+    /// Pricing after the change, zero and empty when unpriced or removed
+    pub price_quantity: f32,
+    pub price_currency: String,
+    /// Pricing before the change, zero and empty when unpriced or added
+    pub previous_price_quantity: f32,
+    pub previous_price_currency: String,
+}
+
+/// Priced listing currently up in a stash, read back from `current_listings` to restore the stash
+/// snapshots
+#[derive(Debug, Row, Serialize, Deserialize)]
+pub struct StashListing {
+    pub stash_id: String,
+    pub league: String,
+    pub item_id: String,
+    pub listing_type: ListingType,
     pub price_quantity: f32,
     pub price_currency: String,
+    pub price_currency_known: bool,
+}
+
+/// Observation of a listing, aggregated by item ID into its lifecycle.
+///
+/// Every column is merged with `min`, `max` or `sum`, so a row only sets what it observed and
+/// leaves the others at a neutral value (the epoch for `max` timestamps).
+#[derive(Debug, Row, Serialize, Deserialize)]
+pub struct ItemListing {
+    pub realm: String,
+    pub league: String,
+    pub item_id: String,
+    pub base: String,
+    pub name: String,
+    pub frame_type: u8,
+    #[serde(with = "clickhouse::serde::chrono::datetime")]
+    pub first_seen: DateTime<Utc>,
+    #[serde(with = "clickhouse::serde::chrono::datetime")]
+    pub last_seen: DateTime<Utc>,
+    /// Last time the listing disappeared from its stash
+    #[serde(with = "clickhouse::serde::chrono::datetime")]
+    pub removed_at: DateTime<Utc>,
+    pub price_changes: u32,
+    /// Unix timestamp, quantity and currency of the earliest and latest observed price
+    pub first_price: (u32, f32, String),
+    pub last_price: (u32, f32, String),
 }
 
 /// Statistics event tracking
@@ -73,12 +155,22 @@ pub struct Item {
 pub struct StatisticsEvent {
     #[serde(with = "clickhouse::serde::chrono::datetime")]
     pub timestamp: DateTime<Utc>,
+    pub realm: String,
     pub stash_count: u32,
     pub item_count: u32,
     pub compressed_bytes: u32,
     pub decompressed_bytes: u32,
 }
 
+/// Crawl position checkpoint, the change ID to resume crawling from
+#[derive(Debug, Row, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(with = "clickhouse::serde::chrono::datetime64::millis")]
+    pub timestamp: DateTime<Utc>,
+    pub realm: String,
+    pub change_id: String,
+}
+
 /// Period types for statistics aggregation
 #[derive(Debug, Serialize_repr, Deserialize_repr)]
 #[repr(i8)]
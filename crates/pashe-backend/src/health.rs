@@ -0,0 +1,76 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Liveness of the crawler, shared between the workers and the health endpoints
+#[derive(Debug)]
+pub struct Health {
+    started_at: Instant,
+    token_valid: AtomicBool,
+    last_ingestion: Mutex<BTreeMap<String, Instant>>,
+}
+
+/// Seconds since the last ingested page of each realm
+#[derive(Debug, Serialize)]
+pub struct IngestionReport {
+    pub stalled: bool,
+    pub seconds_since_last_page: BTreeMap<String, u64>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            token_valid: AtomicBool::new(false),
+            last_ingestion: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set_token_valid(&self, valid: bool) {
+        self.token_valid.store(valid, Ordering::Relaxed);
+    }
+
+    pub fn token_valid(&self) -> bool {
+        self.token_valid.load(Ordering::Relaxed)
+    }
+
+    /// Records that a page of the realm was committed to the database
+    pub fn page_ingested(&self, realm: &str) {
+        self.last_ingestion
+            .lock()
+            .unwrap()
+            .insert(realm.to_string(), Instant::now());
+    }
+
+    /// Reports ingestion as stalled once a realm has gone `stall_timeout` without a page.
+    ///
+    /// Realms that haven't ingested anything yet are measured from startup, which gives the
+    /// crawler the same grace period to get going.
+    pub fn ingestion(&self, realms: &[String], stall_timeout: Duration) -> IngestionReport {
+        let last_ingestion = self.last_ingestion.lock().unwrap();
+
+        let seconds_since_last_page = realms
+            .iter()
+            .map(|realm| {
+                let last_page = last_ingestion.get(realm).unwrap_or(&self.started_at);
+                (realm.clone(), last_page.elapsed().as_secs())
+            })
+            .collect::<BTreeMap<_, _>>();
+        let stalled = seconds_since_last_page
+            .values()
+            .any(|&seconds| seconds >= stall_timeout.as_secs());
+
+        IngestionReport {
+            stalled,
+            seconds_since_last_page,
+        }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
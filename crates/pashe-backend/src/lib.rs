@@ -0,0 +1,8 @@
+pub mod cache;
+pub mod cli;
+pub mod crawler;
+pub mod db;
+pub mod health;
+pub mod poe;
+pub mod server;
+pub mod telemetry;
@@ -0,0 +1,52 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+const PAGE_EXTENSION: &str = ".json.gz";
+
+/// Path of the archived page for a change ID
+pub fn page_path(directory: &Path, change_id: &str) -> PathBuf {
+    directory.join(format!("{change_id}{PAGE_EXTENSION}"))
+}
+
+/// Writes a compressed page to the archive directory
+pub async fn write_page(directory: &Path, change_id: &str, compressed_data: &[u8]) -> Result<()> {
+    tokio::fs::create_dir_all(directory)
+        .await
+        .with_context(|| format!("Failed to create archive directory {}", directory.display()))?;
+
+    let path = page_path(directory, change_id);
+    tokio::fs::write(&path, compressed_data)
+        .await
+        .with_context(|| format!("Failed to write archived page {}", path.display()))
+}
+
+/// Lists the archived pages in crawl order, along with their change IDs
+pub fn list_pages(directory: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut pages = std::fs::read_dir(directory)
+        .with_context(|| format!("Failed to read archive directory {}", directory.display()))?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if !entry.file_type().ok()?.is_file() {
+                return None;
+            }
+            let file_name = entry.file_name().into_string().ok()?;
+            let change_id = file_name.strip_suffix(PAGE_EXTENSION)?.to_string();
+
+            Some((change_id, entry.path()))
+        })
+        .collect::<Vec<_>>();
+
+    pages.sort_by_key(|(change_id, _)| change_id_position(change_id));
+    Ok(pages)
+}
+
+/// Position of a change ID in the chain.
+///
+/// A change ID is a dash-separated list of per-shard counters that only ever grow,
+/// so their sum increases monotonically along the chain.
+fn change_id_position(change_id: &str) -> u64 {
+    change_id
+        .split('-')
+        .filter_map(|counter| counter.parse::<u64>().ok())
+        .sum()
+}
@@ -1,19 +1,33 @@
+use crate::{cache::TokenCache, health::Health};
 use anyhow::Result;
+use async_trait::async_trait;
 use oauth2::basic::BasicClient;
 use oauth2::{ClientId, ClientSecret, Scope, TokenResponse, TokenUrl};
-use std::env;
+use pashe_config::{Config, OAuthConfig};
+use reqwest::header::{AUTHORIZATION, HeaderValue};
+use reqwest::{Response, StatusCode};
+use reqwest_middleware::{Middleware, Next};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
 
-#[tracing::instrument(skip_all, level = "trace")]
-pub async fn fetch_access_token(http_client: &reqwest::Client) -> Result<String> {
-    let client_id = env::var("CLIENT_ID").expect("Missing the CLIENT_ID environment variable.");
-    let client_secret =
-        env::var("CLIENT_SECRET").expect("Missing the CLIENT_SECRET environment variable.");
+/// OAuth access token and its remaining lifetime, if the server gave one
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub secret: String,
+    pub expires_in: Option<Duration>,
+}
 
+#[tracing::instrument(skip_all, level = "trace")]
+pub async fn fetch_access_token(
+    http_client: &reqwest::Client,
+    config: &OAuthConfig,
+) -> Result<AccessToken> {
     let scope = "service:psapi";
-    let token_url = TokenUrl::new("https://www.pathofexile.com/oauth/token".to_string())?;
+    let token_url = TokenUrl::new(config.token_url.clone())?;
 
-    let client = BasicClient::new(ClientId::new(client_id))
-        .set_client_secret(ClientSecret::new(client_secret))
+    let client = BasicClient::new(ClientId::new(config.client_id.clone()))
+        .set_client_secret(ClientSecret::new(config.client_secret.clone()))
         .set_token_uri(token_url);
 
     let token_result = client
@@ -22,5 +36,105 @@ pub async fn fetch_access_token(http_client: &reqwest::Client) -> Result<String>
         .request_async(http_client)
         .await?;
 
-    Ok(token_result.access_token().secret().to_string())
+    Ok(AccessToken {
+        secret: token_result.access_token().secret().to_string(),
+        expires_in: token_result.expires_in(),
+    })
+}
+
+/// Authorizes API requests, fetching a new access token when the current one gets rejected
+#[derive(Debug)]
+pub struct AuthorizationMiddleware {
+    http_client: reqwest::Client,
+    config: Arc<Config>,
+    token_cache: Arc<dyn TokenCache>,
+    health: Arc<Health>,
+    access_token: RwLock<String>,
+    /// Held while refreshing, so that concurrent rejections only trigger a single refresh
+    refresh_lock: tokio::sync::Mutex<()>,
+}
+
+impl AuthorizationMiddleware {
+    /// `http_client` is used for the token endpoint, it must not go through this middleware
+    pub fn new(
+        http_client: reqwest::Client,
+        config: Arc<Config>,
+        token_cache: Arc<dyn TokenCache>,
+        health: Arc<Health>,
+        access_token: String,
+    ) -> Self {
+        Self {
+            http_client,
+            config,
+            token_cache,
+            health,
+            access_token: RwLock::new(access_token),
+            refresh_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn current_token(&self) -> String {
+        self.access_token.read().unwrap().clone()
+    }
+
+    /// Replaces a rejected token, unless another request already did
+    async fn refresh(&self, rejected_token: &str) -> Result<()> {
+        let _refresh_guard = self.refresh_lock.lock().await;
+        if self.current_token() != rejected_token {
+            return Ok(());
+        }
+
+        info!("Access token rejected, fetching a new one");
+        if let Err(e) = self.token_cache.invalidate().await {
+            warn!("Failed to invalidate the cached access token: {:#}", e);
+        }
+
+        let access_token = fetch_access_token(&self.http_client, self.config.oauth()?).await?;
+        self.token_cache.set(&access_token).await?;
+        *self.access_token.write().unwrap() = access_token.secret;
+        self.health.set_token_valid(true);
+
+        Ok(())
+    }
+}
+
+fn authorize(req: &mut reqwest::Request, token: &str) -> reqwest_middleware::Result<()> {
+    let header = HeaderValue::from_str(&format!("Bearer {token}"))
+        .map_err(|e| reqwest_middleware::Error::Middleware(e.into()))?;
+    req.headers_mut().insert(AUTHORIZATION, header);
+    Ok(())
+}
+
+#[async_trait]
+impl Middleware for AuthorizationMiddleware {
+    #[tracing::instrument(skip_all, level = "trace")]
+    async fn handle(
+        &self,
+        mut req: reqwest::Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let token = self.current_token();
+        authorize(&mut req, &token)?;
+
+        // Keep a copy to replay the request with a new token
+        let retry_req = req.try_clone();
+        let res = next.clone().run(req, extensions).await?;
+
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        let Some(mut retry_req) = retry_req else {
+            return Ok(res);
+        };
+
+        if let Err(e) = self.refresh(&token).await {
+            warn!("Failed to refresh the access token: {:#}", e);
+            self.health.set_token_valid(false);
+            return Ok(res);
+        }
+
+        authorize(&mut retry_req, &self.current_token())?;
+        next.run(retry_req, extensions).await
+    }
 }
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+/// Tracks which pages of the change ID chain have been committed.
+///
+/// Pages are fetched concurrently and can finish out of order, so the resume
+/// point only advances once every earlier page has been committed too. A page
+/// counts as committed once its rows are inserted, or once it is saved to the
+/// dead letters when it can't be parsed.
+#[derive(Debug, Default)]
+pub struct CrawlProgress {
+    next_sequence: u64,
+    completed: BTreeMap<u64, String>,
+}
+
+impl CrawlProgress {
+    /// Marks the page at `sequence` as committed.
+    ///
+    /// Returns the change ID to resume from if the committed prefix of the chain advanced.
+    pub fn complete(&mut self, sequence: u64, next_change_id: String) -> Option<String> {
+        if sequence < self.next_sequence {
+            return None;
+        }
+        self.completed.insert(sequence, next_change_id);
+
+        let mut resume_change_id = None;
+        while let Some(change_id) = self.completed.remove(&self.next_sequence) {
+            resume_change_id = Some(change_id);
+            self.next_sequence += 1;
+        }
+
+        resume_change_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advances_with_pages_completed_in_order() {
+        let mut progress = CrawlProgress::default();
+
+        assert_eq!(progress.complete(0, "b".to_string()), Some("b".to_string()));
+        assert_eq!(progress.complete(1, "c".to_string()), Some("c".to_string()));
+    }
+
+    #[test]
+    fn waits_for_earlier_pages_completed_out_of_order() {
+        let mut progress = CrawlProgress::default();
+
+        assert_eq!(progress.complete(2, "d".to_string()), None);
+        assert_eq!(progress.complete(1, "c".to_string()), None);
+        // The whole prefix is committed at once, resuming after the last page
+        assert_eq!(progress.complete(0, "b".to_string()), Some("d".to_string()));
+        assert!(progress.completed.is_empty());
+    }
+
+    #[test]
+    fn holds_back_at_a_gap_in_the_chain() {
+        let mut progress = CrawlProgress::default();
+
+        assert_eq!(progress.complete(0, "b".to_string()), Some("b".to_string()));
+        assert_eq!(progress.complete(2, "d".to_string()), None);
+        assert_eq!(progress.complete(3, "e".to_string()), None);
+        assert_eq!(progress.completed.len(), 2);
+
+        assert_eq!(progress.complete(1, "c".to_string()), Some("e".to_string()));
+        // Pages behind the resume point are ignored
+        assert_eq!(progress.complete(1, "c".to_string()), None);
+    }
+
+    #[test]
+    fn holds_back_at_a_failed_page_until_it_is_set_aside() {
+        let mut progress = CrawlProgress::default();
+
+        assert_eq!(progress.complete(0, "b".to_string()), Some("b".to_string()));
+        // Page 1 failed and is fetched again while the next pages commit
+        for (sequence, next_change_id) in [(2, "d"), (3, "e"), (4, "f")] {
+            assert_eq!(
+                progress.complete(sequence, next_change_id.to_string()),
+                None
+            );
+        }
+
+        // Once dead-lettered, the resume point skips over it
+        assert_eq!(progress.complete(1, "c".to_string()), Some("f".to_string()));
+        assert!(progress.completed.is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+//! Catalogue of the tradable currencies, resolving the currency text of a price note.
+//!
+//! The catalogue is read from a data file listing each currency with its trade site ID, the games
+//! it is traded in and the aliases sellers write in their notes (`c`, `div`, `ex`, ...).
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Currency of a listing price
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListingCurrency {
+    /// Trade site ID of a currency of the catalogue
+    Known(String),
+    /// Lowercased currency text of a note that matches no currency of the catalogue
+    Unknown(String),
+}
+
+impl ListingCurrency {
+    pub fn is_known(&self) -> bool {
+        matches!(self, ListingCurrency::Known(_))
+    }
+}
+
+impl fmt::Display for ListingCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListingCurrency::Known(id) | ListingCurrency::Unknown(id) => f.write_str(id),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CurrenciesFile {
+    currencies: Vec<CurrencyEntry>,
+}
+
+/// A currency, its name and games are only there for the maintainers of the file
+#[derive(Debug, Deserialize)]
+struct CurrencyEntry {
+    id: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+}
+
+/// Currency IDs by their lowercased IDs and aliases
+#[derive(Debug, Default)]
+pub struct CurrencyCatalogue {
+    names: HashMap<String, String>,
+    count: usize,
+}
+
+impl CurrencyCatalogue {
+    /// Loads the currency catalogue from a data file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read currency catalogue {}", path.display()))?;
+        Self::from_json(&contents)
+            .with_context(|| format!("Failed to parse currency catalogue {}", path.display()))
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self> {
+        let currencies_file: CurrenciesFile = serde_json::from_str(contents)?;
+
+        let count = currencies_file.currencies.len();
+        let mut names = HashMap::with_capacity(count * 2);
+        for currency in currencies_file.currencies {
+            for name in std::iter::once(&currency.id).chain(&currency.aliases) {
+                // A note can only stand for one currency
+                if let Some(other) = names.insert(name.to_lowercase(), currency.id.clone())
+                    && other != currency.id
+                {
+                    bail!("`{name}` names both {other} and {}", currency.id);
+                }
+            }
+        }
+
+        Ok(Self { names, count })
+    }
+
+    /// Number of currencies in the catalogue
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Resolves the currency text of a note, case-insensitively
+    pub fn resolve(&self, text: &str) -> ListingCurrency {
+        let text = text.to_lowercase();
+        match self.names.get(&text) {
+            Some(id) => ListingCurrency::Known(id.clone()),
+            None => ListingCurrency::Unknown(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_the_bundled_currencies_and_their_aliases() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../data/currencies.json");
+        let currencies = CurrencyCatalogue::load(&path).unwrap();
+
+        assert!(!currencies.is_empty());
+        for (text, id) in [
+            ("chaos", "chaos"),
+            ("C", "chaos"),
+            ("div", "divine"),
+            ("chrome", "chrome"),
+            ("jew", "jewellers"),
+            ("gcp", "gcp"),
+            ("vaal", "vaal"),
+            ("greater-exalted-orb", "greater-exalted-orb"),
+        ] {
+            assert_eq!(
+                currencies.resolve(text),
+                ListingCurrency::Known(id.to_string())
+            );
+        }
+        assert_eq!(
+            currencies.resolve("Unknown-Orb"),
+            ListingCurrency::Unknown("unknown-orb".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_aliases_shared_by_two_currencies() {
+        let contents = r#"{
+            "currencies": [
+                {"id": "chaos", "name": "Chaos Orb", "games": ["poe1"], "aliases": ["c"]},
+                {"id": "chance", "name": "Orb of Chance", "games": ["poe1"], "aliases": ["c"]}
+            ]
+        }"#;
+
+        assert!(CurrencyCatalogue::from_json(contents).is_err());
+    }
+}
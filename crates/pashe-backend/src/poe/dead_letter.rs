@@ -0,0 +1,116 @@
+use crate::poe::archive;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+const FETCH_FAILURES_FILE: &str = "fetch_failures.jsonl";
+/// Subdirectory of the pages that could not be parsed, archived along with their failure
+const PAGES_DIRECTORY: &str = "pages";
+const FAILURE_EXTENSION: &str = ".error.json";
+
+/// A change ID that could not be fetched
+#[derive(Debug, Serialize)]
+struct FetchFailure<'a> {
+    change_id: &'a str,
+    reason: &'a str,
+    failed_at: DateTime<Utc>,
+}
+
+/// Appends a change ID that kept failing to the fetch failures log of the directory
+pub async fn record_fetch_failure(directory: &Path, change_id: &str, reason: &str) -> Result<()> {
+    tokio::fs::create_dir_all(directory)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to create dead-letter directory {}",
+                directory.display()
+            )
+        })?;
+
+    let mut line = serde_json::to_vec(&FetchFailure {
+        change_id,
+        reason,
+        failed_at: Utc::now(),
+    })?;
+    line.push(b'\n');
+
+    let path = directory.join(FETCH_FAILURES_FILE);
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(&line)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    // Tokio writes in the background, make sure the entry made it to the file
+    file.flush().await?;
+
+    Ok(())
+}
+
+/// A page that could not be parsed
+#[derive(Debug, Serialize)]
+struct PageFailure<'a> {
+    change_id: &'a str,
+    /// Path of the value that failed to parse within the page
+    path: &'a str,
+    error: &'a str,
+    failed_at: DateTime<Utc>,
+}
+
+/// Saves a compressed page that could not be parsed, with the path and error it failed at, so
+/// that it can be retried once the stash types are fixed
+pub async fn record_page(
+    directory: &Path,
+    change_id: &str,
+    path: &str,
+    error: &str,
+    compressed_data: &[u8],
+) -> Result<()> {
+    let pages_directory = directory.join(PAGES_DIRECTORY);
+    archive::write_page(&pages_directory, change_id, compressed_data).await?;
+
+    let failure = serde_json::to_vec_pretty(&PageFailure {
+        change_id,
+        path,
+        error,
+        failed_at: Utc::now(),
+    })?;
+    let failure_path = failure_path(&pages_directory, change_id);
+    tokio::fs::write(&failure_path, failure)
+        .await
+        .with_context(|| format!("Failed to write {}", failure_path.display()))
+}
+
+/// Lists the dead-lettered pages in crawl order, along with their change IDs
+pub fn list_pages(directory: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let pages_directory = directory.join(PAGES_DIRECTORY);
+    if !pages_directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    archive::list_pages(&pages_directory)
+}
+
+/// Removes a dead-lettered page once it went through
+pub async fn remove_page(directory: &Path, change_id: &str) -> Result<()> {
+    let pages_directory = directory.join(PAGES_DIRECTORY);
+    for path in [
+        archive::page_path(&pages_directory, change_id),
+        failure_path(&pages_directory, change_id),
+    ] {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn failure_path(pages_directory: &Path, change_id: &str) -> PathBuf {
+    pages_directory.join(format!("{change_id}{FAILURE_EXTENSION}"))
+}
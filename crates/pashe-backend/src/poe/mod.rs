@@ -1,5 +1,17 @@
+pub mod archive;
 pub mod authorization;
+pub mod checkpoint;
 pub mod constants;
+pub mod currency;
+pub mod dead_letter;
+pub mod page_decoder;
+pub mod price;
+pub mod pseudo;
 pub mod public_stash_worker;
 pub mod rate_limit;
+pub mod realm;
+pub mod retry;
+pub mod schema_drift;
+pub mod stash_diff;
+pub mod stats;
 pub mod types;
@@ -0,0 +1,317 @@
+use crate::poe::schema_drift::SchemaDrift;
+use crate::poe::types::Stash;
+use anyhow::Result;
+use async_compression::tokio::bufread::GzipDecoder;
+use bytes::Bytes;
+use futures_util::{Stream, TryStreamExt};
+use serde::Deserialize;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+/// Byte counts and chain position of a fully decoded page
+#[derive(Debug)]
+pub struct PageSummary {
+    pub next_change_id: String,
+    pub compressed_bytes: u32,
+    pub decompressed_bytes: u32,
+}
+
+/// A page that could not be parsed, along with the position in the page where parsing stopped
+#[derive(Debug, Error)]
+#[error("Failed to decode page from {origin} at `{path}`")]
+pub struct DecodeError {
+    pub origin: String,
+    /// Path of the value that failed to parse, e.g. `stashes[3].items[0].sockets`
+    pub path: String,
+    #[source]
+    pub error: serde_json::Error,
+}
+
+impl DecodeError {
+    /// Whether the page could not be read in full, rather than containing unexpected data
+    pub fn is_io(&self) -> bool {
+        self.error.is_io()
+    }
+}
+
+/// Decompresses and parses a gzip encoded public stash page as it streams in.
+///
+/// Each stash is sent to `stashes_tx` as soon as it is parsed, so neither the compressed nor the
+/// decompressed page is ever held in memory as a whole. Stashes sampled by `schema_drift` are the
+/// exception, they are parsed through an intermediate JSON value to be inspected.
+pub async fn decode_page<S>(
+    compressed_stream: S,
+    stashes_tx: mpsc::Sender<Stash>,
+    schema_drift: Option<Arc<SchemaDrift>>,
+    source: &str,
+) -> Result<PageSummary>
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+{
+    let compressed_bytes = Arc::new(AtomicU32::new(0));
+    let compressed_stream = compressed_stream.inspect_ok({
+        let compressed_bytes = Arc::clone(&compressed_bytes);
+        move |chunk| {
+            compressed_bytes.fetch_add(chunk.len() as u32, Ordering::Relaxed);
+        }
+    });
+
+    let gzip_decoder = GzipDecoder::new(StreamReader::new(compressed_stream));
+    // The bridge must be created from within the runtime, it is then driven from a blocking thread
+    let decompressed_reader = SyncIoBridge::new(gzip_decoder);
+
+    let (next_change_id, decompressed_bytes) = tokio::task::spawn_blocking(move || {
+        let mut counting_reader = CountingReader::new(decompressed_reader);
+        let mut deserializer =
+            serde_json::Deserializer::from_reader(io::BufReader::new(&mut counting_reader));
+
+        let mut track = serde_path_to_error::Track::new();
+
+        let next_change_id = PageVisitor {
+            stashes_tx: &stashes_tx,
+            schema_drift: schema_drift.as_deref(),
+        }
+        .deserialize(serde_path_to_error::Deserializer::new(
+            &mut deserializer,
+            &mut track,
+        ))
+        .and_then(|next_change_id| deserializer.end().map(|()| next_change_id))
+        .map_err(|error| (error, track.path().to_string()))?;
+        drop(deserializer);
+
+        Ok((next_change_id, counting_reader.count))
+    })
+    .await?
+    .map_err(|(error, path)| DecodeError {
+        origin: source.to_string(),
+        path,
+        error,
+    })?;
+
+    Ok(PageSummary {
+        next_change_id,
+        compressed_bytes: compressed_bytes.load(Ordering::Relaxed),
+        decompressed_bytes,
+    })
+}
+
+/// Counts the bytes read through it
+struct CountingReader<R> {
+    inner: R,
+    count: u32,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u32;
+        Ok(read)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum PageField {
+    NextChangeId,
+    Stashes,
+    #[serde(other)]
+    Other,
+}
+
+/// Visits the top-level page object (`next_change_id` and `stashes`), forwarding stashes and
+/// returning the next change ID
+struct PageVisitor<'a> {
+    stashes_tx: &'a mpsc::Sender<Stash>,
+    schema_drift: Option<&'a SchemaDrift>,
+}
+
+impl<'de> DeserializeSeed<'de> for PageVisitor<'_> {
+    type Value = String;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for PageVisitor<'_> {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a public stash page")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<String, A::Error> {
+        let mut next_change_id = None;
+
+        while let Some(field) = map.next_key::<PageField>()? {
+            match field {
+                PageField::NextChangeId => next_change_id = Some(map.next_value()?),
+                PageField::Stashes => map.next_value_seed(StashesVisitor {
+                    stashes_tx: self.stashes_tx,
+                    schema_drift: self.schema_drift,
+                })?,
+                PageField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        next_change_id.ok_or_else(|| de::Error::missing_field("next_change_id"))
+    }
+}
+
+/// Visits the stashes array, sending each stash as soon as it is parsed
+struct StashesVisitor<'a> {
+    stashes_tx: &'a mpsc::Sender<Stash>,
+    schema_drift: Option<&'a SchemaDrift>,
+}
+
+impl<'de> DeserializeSeed<'de> for StashesVisitor<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for StashesVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of stashes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        loop {
+            let stash = match self.schema_drift {
+                Some(schema_drift) if schema_drift.sample() => {
+                    match seq.next_element::<serde_json::Value>()? {
+                        Some(value) => schema_drift.inspect(value).map_err(de::Error::custom)?,
+                        None => break,
+                    }
+                }
+                _ => match seq.next_element::<Stash>()? {
+                    Some(stash) => stash,
+                    None => break,
+                },
+            };
+
+            self.stashes_tx
+                .blocking_send(stash)
+                .map_err(|_| de::Error::custom("stash receiver dropped"))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poe::types::fixtures::item;
+    use async_compression::tokio::bufread::GzipEncoder;
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+
+    fn page() -> String {
+        let stash = |id: &str, item_id: &str| {
+            json!({
+                "id": id,
+                "public": true,
+                "stashType": "PremiumStash",
+                "items": [item(json!({"id": item_id, "note": "~b/o 1 chaos"}))],
+            })
+        };
+
+        json!({
+            "next_change_id": "2-2-2-2-2",
+            "stashes": [stash("stash-a", "item-a"), stash("stash-b", "item-b")],
+        })
+        .to_string()
+    }
+
+    async fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        GzipEncoder::new(body)
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        compressed
+    }
+
+    /// Decodes a page streamed in small chunks, returning the stashes received along the way
+    async fn decode(compressed: Vec<u8>) -> (Result<PageSummary>, Vec<Stash>) {
+        let chunks: Vec<io::Result<Bytes>> = compressed
+            .chunks(16)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let (stashes_tx, mut stashes_rx) = mpsc::channel(8);
+
+        let result =
+            decode_page(futures_util::stream::iter(chunks), stashes_tx, None, "test").await;
+
+        let mut stashes = Vec::new();
+        while let Some(stash) = stashes_rx.recv().await {
+            stashes.push(stash);
+        }
+        (result, stashes)
+    }
+
+    #[tokio::test]
+    async fn decodes_a_page_and_counts_its_bytes() {
+        let body = page();
+        let compressed = gzip(body.as_bytes()).await;
+        let compressed_len = compressed.len();
+
+        let (result, stashes) = decode(compressed).await;
+
+        let summary = result.unwrap();
+        assert_eq!(summary.next_change_id, "2-2-2-2-2");
+        assert_eq!(summary.compressed_bytes as usize, compressed_len);
+        assert_eq!(summary.decompressed_bytes as usize, body.len());
+        let ids: Vec<_> = stashes.iter().map(|stash| stash.id.as_str()).collect();
+        assert_eq!(ids, ["stash-a", "stash-b"]);
+        assert_eq!(stashes[1].items[0].id, "item-b");
+    }
+
+    #[tokio::test]
+    async fn reports_where_a_truncated_page_stopped() {
+        let body = page();
+        // Cut the page off in the middle of the second stash
+        let cut = body.find("stash-b").unwrap() + 3;
+        let compressed = gzip(&body.as_bytes()[..cut]).await;
+
+        let (result, stashes) = decode(compressed).await;
+
+        let error = result.unwrap_err();
+        let error = error.downcast_ref::<DecodeError>().unwrap();
+        assert!(error.path.starts_with("stashes[1]"), "{}", error.path);
+        assert!(error.error.is_eof());
+        // The stashes parsed before the cut were already sent
+        let ids: Vec<_> = stashes.iter().map(|stash| stash.id.as_str()).collect();
+        assert_eq!(ids, ["stash-a"]);
+    }
+
+    #[tokio::test]
+    async fn reports_a_page_whose_stream_ended_early() {
+        let mut compressed = gzip(page().as_bytes()).await;
+        compressed.truncate(compressed.len() / 2);
+
+        let (result, _) = decode(compressed).await;
+
+        let error = result.unwrap_err();
+        assert!(error.downcast_ref::<DecodeError>().unwrap().is_io());
+    }
+}
@@ -0,0 +1,162 @@
+//! Grammar of the price notes set on items and stash tabs.
+//!
+//! ```text
+//! note     = listing, spaces, amount, [spaces], currency, [anything]
+//! listing  = "~price" | "~b/o"
+//! amount   = number, [[spaces], "/", [spaces], number]
+//! currency = word, resolved against the currency catalogue, e.g. "chaos", "c", "div", "ex"
+//! ```
+//!
+//! Prefixes and currencies are matched case-insensitively, fractions such as `1/3` are turned
+//! into decimal quantities and the text following the currency is ignored. Currencies missing
+//! from the catalogue are kept as unknown rather than failing the note.
+
+use crate::db::ListingType;
+use crate::poe::currency::{CurrencyCatalogue, ListingCurrency};
+use winnow::ascii::{Caseless, space0, space1};
+use winnow::combinator::{alt, opt, preceded};
+use winnow::prelude::*;
+use winnow::token::take_while;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingPrice {
+    pub listing_type: ListingType,
+    pub quantity: f32,
+    pub currency: ListingCurrency,
+}
+
+/// Parses a price note, returning `None` when the note isn't a valid price
+pub fn parse_note(note: &str, currencies: &CurrencyCatalogue) -> Option<ListingPrice> {
+    let (listing_type, _, quantity, _, currency) = (listing_type, space1, amount, space0, currency)
+        .parse_next(&mut note.trim_start())
+        .ok()?;
+
+    if !quantity.is_finite() || quantity <= 0.0 {
+        return None;
+    }
+
+    Some(ListingPrice {
+        listing_type,
+        quantity,
+        currency: currencies.resolve(currency),
+    })
+}
+
+fn listing_type(input: &mut &str) -> winnow::Result<ListingType> {
+    alt((
+        Caseless("~price").value(ListingType::Price),
+        Caseless("~b/o").value(ListingType::Buyout),
+    ))
+    .parse_next(input)
+}
+
+/// A decimal number or a fraction of two of them
+fn amount(input: &mut &str) -> winnow::Result<f32> {
+    let numerator = number.parse_next(input)?;
+    let denominator = opt(preceded((space0, '/', space0), number)).parse_next(input)?;
+
+    Ok(match denominator {
+        Some(denominator) => numerator / denominator,
+        None => numerator,
+    })
+}
+
+fn number(input: &mut &str) -> winnow::Result<f32> {
+    take_while(1.., |c: char| c.is_ascii_digit() || c == '.')
+        .try_map(str::parse::<f32>)
+        .parse_next(input)
+}
+
+/// The currency word, anything after it is left for the seller's comments
+fn currency<'a>(input: &mut &'a str) -> winnow::Result<&'a str> {
+    take_while(1.., |c: char| c.is_alphanumeric() || c == '-' || c == '\'').parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    type Expected = Option<(ListingType, f32, &'static str)>;
+
+    /// Notes seen on the public stash API, with the price they stand for
+    const CORPUS: &[(&str, Expected)] = &[
+        ("~price 1 chaos", Some((ListingType::Price, 1.0, "chaos"))),
+        ("~b/o 5 chaos", Some((ListingType::Buyout, 5.0, "chaos"))),
+        (
+            "~b/o 2.5 divine",
+            Some((ListingType::Buyout, 2.5, "divine")),
+        ),
+        (
+            "~b/o 1/2 divine",
+            Some((ListingType::Buyout, 0.5, "divine")),
+        ),
+        (
+            "~price 3 / 4 div",
+            Some((ListingType::Price, 0.75, "divine")),
+        ),
+        ("~b/o 20c", Some((ListingType::Buyout, 20.0, "chaos"))),
+        ("~b/o 1 ex", Some((ListingType::Buyout, 1.0, "exalted"))),
+        ("~price 2 exa", Some((ListingType::Price, 2.0, "exalted"))),
+        ("~B/O 10 Chaos", Some((ListingType::Buyout, 10.0, "chaos"))),
+        (
+            "~price 150 alch each, bulk only",
+            Some((ListingType::Price, 150.0, "alch")),
+        ),
+        ("~b/o 1 mirror", Some((ListingType::Buyout, 1.0, "mirror"))),
+        ("~price 40 fuse", Some((ListingType::Price, 40.0, "fusing"))),
+        ("~price 12 alt.", Some((ListingType::Price, 12.0, "alt"))),
+        ("  ~b/o 3 regal", Some((ListingType::Buyout, 3.0, "regal"))),
+        (
+            "~b/o 30 chrome",
+            Some((ListingType::Buyout, 30.0, "chrome")),
+        ),
+        (
+            "~b/o 15 jew",
+            Some((ListingType::Buyout, 15.0, "jewellers")),
+        ),
+        ("~price 1 gcp", Some((ListingType::Price, 1.0, "gcp"))),
+        ("~b/o 4 vaal", Some((ListingType::Buyout, 4.0, "vaal"))),
+        (
+            "~b/o 2 greater-exalted-orb",
+            Some((ListingType::Buyout, 2.0, "greater-exalted-orb")),
+        ),
+        (
+            "~price 5 unknown-orb",
+            Some((ListingType::Price, 5.0, "unknown-orb")),
+        ),
+        ("~price 0 chaos", None),
+        ("~b/o 1/0 chaos", None),
+        ("~b/o chaos", None),
+        ("~skip", None),
+        ("Dump tab", None),
+        ("price 5 chaos", None),
+        ("", None),
+    ];
+
+    #[test]
+    fn parses_the_note_corpus() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../data/currencies.json");
+        let currencies = CurrencyCatalogue::load(&path).unwrap();
+
+        for (note, expected) in CORPUS {
+            let parsed = parse_note(note, &currencies).map(|price| {
+                (
+                    price.listing_type,
+                    price.quantity,
+                    price.currency.to_string(),
+                )
+            });
+            let expected = expected.map(|(listing_type, quantity, currency)| {
+                (listing_type, quantity, currency.to_string())
+            });
+            assert_eq!(parsed, expected, "{note:?}");
+        }
+
+        let unknown = parse_note("~price 5 Unknown-Orb", &currencies).unwrap();
+        assert_eq!(
+            unknown.currency,
+            ListingCurrency::Unknown("unknown-orb".to_string())
+        );
+    }
+}
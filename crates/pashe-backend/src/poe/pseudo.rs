@@ -0,0 +1,208 @@
+//! Pseudo stats, the totals the trade site derives from the mods of an item so that rares can be
+//! compared regardless of which mods make up the totals.
+
+use crate::poe::stats::ItemStats;
+use crate::poe::types::Item;
+
+// Trade site stat IDs, shared by every kind of mod
+const MAXIMUM_LIFE: &str = "stat_3299347043";
+const MAXIMUM_MANA: &str = "stat_1050105434";
+const MAXIMUM_ENERGY_SHIELD: &str = "stat_3489782002";
+const FIRE_RESISTANCE: &str = "stat_3372524247";
+const COLD_RESISTANCE: &str = "stat_4220027924";
+const LIGHTNING_RESISTANCE: &str = "stat_1671376347";
+const FIRE_AND_COLD_RESISTANCES: &str = "stat_2915988346";
+const FIRE_AND_LIGHTNING_RESISTANCES: &str = "stat_3441501978";
+const COLD_AND_LIGHTNING_RESISTANCES: &str = "stat_4277795662";
+const ALL_ELEMENTAL_RESISTANCES: &str = "stat_2901986750";
+const CHAOS_RESISTANCE: &str = "stat_2923486259";
+const STRENGTH: &str = "stat_4080418644";
+const DEXTERITY: &str = "stat_3261801346";
+const INTELLIGENCE: &str = "stat_328541901";
+const STRENGTH_AND_DEXTERITY: &str = "stat_538848803";
+const STRENGTH_AND_INTELLIGENCE: &str = "stat_1535626285";
+const DEXTERITY_AND_INTELLIGENCE: &str = "stat_2300185227";
+const ALL_ATTRIBUTES: &str = "stat_1379411836";
+
+/// Frame types of the items that can have affixes
+const MAGIC_FRAME_TYPE: u8 = 1;
+const RARE_FRAME_TYPE: u8 = 2;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PseudoStats {
+    /// Including the life granted by strength
+    pub total_life: f32,
+    /// Including the mana granted by intelligence
+    pub total_mana: f32,
+    pub total_energy_shield: f32,
+    /// Sum of the fire, cold and lightning resistances
+    pub total_elemental_resistance: f32,
+    pub total_chaos_resistance: f32,
+    pub total_strength: f32,
+    pub total_dexterity: f32,
+    pub total_intelligence: f32,
+    /// Affixes that can still be added, none on unidentified, corrupted and mirrored items
+    pub open_prefixes: u8,
+    pub open_suffixes: u8,
+}
+
+impl PseudoStats {
+    /// Derives the pseudo stats of an item from its parsed stats
+    pub fn compute(item: &Item, stats: &ItemStats) -> Self {
+        let mut pseudo = Self::default();
+
+        for (stat_id, values) in stats.stat_ids.iter().zip(&stats.stat_values) {
+            let Some(&value) = values.first() else {
+                continue;
+            };
+            // Drop the kind of mod, an implicit counts the same as an explicit
+            let stat = stat_id
+                .split_once('.')
+                .map_or(stat_id.as_str(), |(_, stat)| stat);
+
+            match stat {
+                MAXIMUM_LIFE => pseudo.total_life += value,
+                MAXIMUM_MANA => pseudo.total_mana += value,
+                MAXIMUM_ENERGY_SHIELD => pseudo.total_energy_shield += value,
+                FIRE_RESISTANCE | COLD_RESISTANCE | LIGHTNING_RESISTANCE => {
+                    pseudo.total_elemental_resistance += value
+                }
+                FIRE_AND_COLD_RESISTANCES
+                | FIRE_AND_LIGHTNING_RESISTANCES
+                | COLD_AND_LIGHTNING_RESISTANCES => {
+                    pseudo.total_elemental_resistance += 2.0 * value
+                }
+                ALL_ELEMENTAL_RESISTANCES => pseudo.total_elemental_resistance += 3.0 * value,
+                CHAOS_RESISTANCE => pseudo.total_chaos_resistance += value,
+                STRENGTH => pseudo.total_strength += value,
+                DEXTERITY => pseudo.total_dexterity += value,
+                INTELLIGENCE => pseudo.total_intelligence += value,
+                STRENGTH_AND_DEXTERITY => {
+                    pseudo.total_strength += value;
+                    pseudo.total_dexterity += value;
+                }
+                STRENGTH_AND_INTELLIGENCE => {
+                    pseudo.total_strength += value;
+                    pseudo.total_intelligence += value;
+                }
+                DEXTERITY_AND_INTELLIGENCE => {
+                    pseudo.total_dexterity += value;
+                    pseudo.total_intelligence += value;
+                }
+                ALL_ATTRIBUTES => {
+                    pseudo.total_strength += value;
+                    pseudo.total_dexterity += value;
+                    pseudo.total_intelligence += value;
+                }
+                _ => {}
+            }
+        }
+
+        // Every 2 strength grant 1 life, and every 2 intelligence 1 mana
+        pseudo.total_life += pseudo.total_strength / 2.0;
+        pseudo.total_mana += pseudo.total_intelligence / 2.0;
+
+        (pseudo.open_prefixes, pseudo.open_suffixes) = open_affixes(item);
+        pseudo
+    }
+}
+
+/// Prefixes and suffixes left before the item reaches the maximum of its rarity
+fn open_affixes(item: &Item) -> (u8, u8) {
+    // Affixes are hidden until identified, and corrupted or mirrored items can't be crafted on
+    if !item.identified || item.corrupted.unwrap_or(false) || item.duplicated.unwrap_or(false) {
+        return (0, 0);
+    }
+    let Some(extended) = &item.extended else {
+        return (0, 0);
+    };
+
+    let max_affixes = match item.frame_type {
+        MAGIC_FRAME_TYPE => 1,
+        RARE_FRAME_TYPE if item.base_type.ends_with("Jewel") => 2,
+        RARE_FRAME_TYPE => 3,
+        _ => 0,
+    };
+    // The API leaves out the count of the kind of affixes the item has none of
+    let open = |affixes: Option<i64>| (max_affixes - affixes.unwrap_or(0)).clamp(0, 3) as u8;
+
+    (open(extended.prefixes), open(extended.suffixes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poe::types::fixtures;
+    use serde_json::json;
+
+    fn item(frame_type: u8, base_type: &str, extended: serde_json::Value) -> Item {
+        serde_json::from_value(fixtures::item(json!({
+            "typeLine": base_type,
+            "baseType": base_type,
+            "frameType": frame_type,
+            "extended": extended,
+        })))
+        .unwrap()
+    }
+
+    fn stats(stats: &[(&str, f32)]) -> ItemStats {
+        ItemStats {
+            stat_ids: stats.iter().map(|(id, _)| id.to_string()).collect(),
+            stat_values: stats.iter().map(|(_, value)| vec![*value]).collect(),
+            unmatched: 0,
+        }
+    }
+
+    #[test]
+    fn totals_resistances_and_attributes() {
+        let item = item(RARE_FRAME_TYPE, "Vaal Regalia", json!({}));
+        let stats = stats(&[
+            ("explicit.stat_3299347043", 70.0),
+            ("implicit.stat_3299347043", 20.0),
+            ("explicit.stat_3372524247", 40.0),
+            ("crafted.stat_4277795662", 15.0),
+            ("explicit.stat_2901986750", 10.0),
+            ("explicit.stat_4080418644", 30.0),
+            ("explicit.stat_1379411836", 10.0),
+        ]);
+
+        let pseudo = PseudoStats::compute(&item, &stats);
+
+        assert_eq!(pseudo.total_elemental_resistance, 40.0 + 30.0 + 30.0);
+        assert_eq!(pseudo.total_strength, 40.0);
+        assert_eq!(pseudo.total_intelligence, 10.0);
+        assert_eq!(pseudo.total_life, 90.0 + 20.0);
+        assert_eq!(pseudo.total_mana, 5.0);
+    }
+
+    #[test]
+    fn counts_open_affixes_by_rarity() {
+        let rare = item(
+            RARE_FRAME_TYPE,
+            "Vaal Regalia",
+            json!({"prefixes": 2, "suffixes": 3}),
+        );
+        let jewel = item(RARE_FRAME_TYPE, "Cobalt Jewel", json!({"prefixes": 1}));
+        let magic = item(MAGIC_FRAME_TYPE, "Vaal Regalia", json!({}));
+        let unique = item(3, "Vaal Regalia", json!({}));
+
+        assert_eq!(open_affixes(&rare), (1, 0));
+        assert_eq!(open_affixes(&jewel), (1, 2));
+        assert_eq!(open_affixes(&magic), (1, 1));
+        assert_eq!(open_affixes(&unique), (0, 0));
+    }
+
+    #[test]
+    fn has_no_open_affixes_when_it_cant_be_crafted_on() {
+        let mut unidentified = item(RARE_FRAME_TYPE, "Vaal Regalia", json!({}));
+        unidentified.identified = false;
+        let mut corrupted = item(RARE_FRAME_TYPE, "Vaal Regalia", json!({"prefixes": 1}));
+        corrupted.corrupted = Some(true);
+        let mut mirrored = item(RARE_FRAME_TYPE, "Vaal Regalia", json!({"suffixes": 1}));
+        mirrored.duplicated = Some(true);
+
+        assert_eq!(open_affixes(&unidentified), (0, 0));
+        assert_eq!(open_affixes(&corrupted), (0, 0));
+        assert_eq!(open_affixes(&mirrored), (0, 0));
+    }
+}
@@ -1,63 +1,286 @@
 use crate::{
-    db::{self, ListingCurrency, StatisticsEvent},
-    poe::{constants::BASE_URL, types::PublicStashTabs},
+    db::{self, Checkpoint, ItemListing, StashEvent, StashEventKind, StatisticsEvent},
+    health::Health,
+    poe::{
+        archive,
+        checkpoint::CrawlProgress,
+        constants::BASE_URL,
+        currency::CurrencyCatalogue,
+        dead_letter,
+        page_decoder::{self, DecodeError, PageSummary},
+        price::{self, ListingPrice},
+        pseudo::PseudoStats,
+        realm::Realm,
+        retry,
+        schema_drift::SchemaDrift,
+        stash_diff::{StashContents, StashSnapshots},
+        stats::StatTemplates,
+        types::Stash,
+    },
+    telemetry,
 };
 use anyhow::{Context, Result};
-use async_compression::tokio::bufread::GzipDecoder;
-use chrono::Utc;
-use futures_util::StreamExt;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
 use human_repr::{HumanCount, HumanDuration, HumanThroughput};
-use std::str::FromStr;
-use std::sync::Arc;
-use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::io::ReaderStream;
 use tokio_util::sync::CancellationToken;
-use tracing::{Instrument, debug, error, info};
-use winnow::prelude::*;
-use winnow::{ascii::multispace1, combinator::alt, token::take_while};
+use tracing::{debug, error, info, warn};
+
+/// Number of parsed stashes buffered between the page decoder and the processor
+const STASH_BUFFER: usize = 16;
+/// Attempts at inserting the rows of a page before the processor gives up
+const INSERT_ATTEMPTS: u32 = 8;
+
+/// A public stash page being decoded
+#[derive(Debug)]
+pub struct StashPage {
+    /// Position of the page in the change ID chain
+    pub sequence: u64,
+    /// Change ID the page was fetched at, identifies its rows when they are inserted again
+    pub change_id: String,
+    /// Stashes of the page, in order, as they are parsed
+    pub stashes: mpsc::Receiver<Stash>,
+    /// Resolves once the page is fully decoded, dropped if decoding failed
+    pub summary: oneshot::Receiver<PageSummary>,
+    /// Notified once the rows of the page are inserted, dropped if they weren't
+    pub inserted: Option<oneshot::Sender<()>>,
+}
+
+impl StashPage {
+    fn new(
+        sequence: u64,
+        change_id: String,
+    ) -> (Self, mpsc::Sender<Stash>, oneshot::Sender<PageSummary>) {
+        let (stashes_tx, stashes) = mpsc::channel(STASH_BUFFER);
+        let (summary_tx, summary) = oneshot::channel();
+
+        (
+            Self {
+                sequence,
+                change_id,
+                stashes,
+                summary,
+                inserted: None,
+            },
+            stashes_tx,
+            summary_tx,
+        )
+    }
+}
 
 #[derive(Debug)]
 pub struct PublicStashWorker {
     shutdown_token: CancellationToken,
+    realm: Realm,
+    api_url: String,
+    health: Arc<Health>,
+    progress: Mutex<CrawlProgress>,
+    snapshots: Mutex<StashSnapshots>,
+    checkpoints: bool,
+    archive_dir: Option<PathBuf>,
+    dead_letter_dir: Option<PathBuf>,
+    schema_drift: Option<Arc<SchemaDrift>>,
+    stat_templates: Arc<StatTemplates>,
+    currencies: Arc<CurrencyCatalogue>,
 }
 
 impl PublicStashWorker {
-    pub fn new(shutdown_token: CancellationToken) -> Self {
-        PublicStashWorker { shutdown_token }
+    pub fn new(shutdown_token: CancellationToken, realm: Realm, health: Arc<Health>) -> Self {
+        PublicStashWorker {
+            shutdown_token,
+            realm,
+            api_url: BASE_URL.to_string(),
+            health,
+            progress: Mutex::new(CrawlProgress::default()),
+            snapshots: Mutex::new(StashSnapshots::default()),
+            checkpoints: true,
+            archive_dir: None,
+            dead_letter_dir: None,
+            schema_drift: None,
+            stat_templates: Arc::new(StatTemplates::default()),
+            currencies: Arc::new(CurrencyCatalogue::default()),
+        }
+    }
+
+    /// Fetches pages from the API at the given base URL rather than the official one
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url;
+        self
+    }
+
+    /// Archives every fetched page to the given directory
+    pub fn with_archive_dir(mut self, archive_dir: Option<PathBuf>) -> Self {
+        self.archive_dir = archive_dir;
+        self
+    }
+
+    /// Logs the change IDs that could not be fetched, and saves the pages that could not be parsed,
+    /// to the given directory
+    pub fn with_dead_letter_dir(mut self, dead_letter_dir: PathBuf) -> Self {
+        self.dead_letter_dir = Some(dead_letter_dir);
+        self
+    }
+
+    /// Inspects a sample of the decoded stashes for fields not covered by the stash types
+    pub fn with_schema_drift(mut self, schema_drift: Option<Arc<SchemaDrift>>) -> Self {
+        self.schema_drift = schema_drift;
+        self
+    }
+
+    /// Matches the mods of the priced items to these stat templates, none are matched by default
+    pub fn with_stat_templates(mut self, stat_templates: Arc<StatTemplates>) -> Self {
+        self.stat_templates = stat_templates;
+        self
+    }
+
+    /// Resolves the currencies of the price notes against this catalogue, all currencies are
+    /// unknown by default
+    pub fn with_currencies(mut self, currencies: Arc<CurrencyCatalogue>) -> Self {
+        self.currencies = currencies;
+        self
+    }
+
+    /// Keeps the last contents of this many stashes in memory, the others are restored from the
+    /// database when they change again
+    pub fn with_snapshot_capacity(mut self, capacity: NonZeroUsize) -> Self {
+        self.snapshots = Mutex::new(StashSnapshots::with_capacity(capacity));
+        self
+    }
+
+    /// Disables checkpointing of the crawl position, e.g. when replaying archived pages
+    pub fn without_checkpoints(mut self) -> Self {
+        self.checkpoints = false;
+        self
     }
 
     /// Crawls a single stash change and sends the next change ID and stash data to respective queues
-    #[tracing::instrument(skip_all, level = "trace")]
+    #[tracing::instrument(skip_all, level = "trace", fields(realm = %self.realm))]
     pub async fn fetch_stash(
         self: Arc<Self>,
         client: Arc<reqwest_middleware::ClientWithMiddleware>,
+        sequence: u64,
         change_id: String,
-        next_change_id_tx: mpsc::UnboundedSender<String>,
-        stash_changes_tx: mpsc::UnboundedSender<(PublicStashTabs, u32, u32)>,
+        next_change_id_tx: mpsc::Sender<(u64, String)>,
+        stash_changes_tx: mpsc::Sender<StashPage>,
     ) -> Result<()> {
         debug!("Fetching change id: {}", change_id);
 
-        let url = format!("{BASE_URL}/public-stash-tabs?id={change_id}");
+        // An empty change ID starts from the beginning of the realm's stream
+        let url = if change_id.is_empty() {
+            self.realm.public_stash_url(&self.api_url)
+        } else {
+            format!(
+                "{}?id={change_id}",
+                self.realm.public_stash_url(&self.api_url)
+            )
+        };
+
+        // Taken once the next change ID is sent, a page fetched again must not send it twice
+        let mut next_change_id_tx = Some(next_change_id_tx);
+        let mut failure_logged = false;
 
-        let response = client.get(url.clone()).send().await?;
+        // The chain can't go on without this page, keep trying once the API had time to recover
+        for round in 1u32.. {
+            // Server and network errors were already retried by the client
+            let (failure, retryable) = match client.get(url.clone()).send().await {
+                Ok(response) if response.status() == reqwest::StatusCode::OK => {
+                    match self
+                        .handle_page(
+                            &client,
+                            response,
+                            sequence,
+                            &change_id,
+                            &mut next_change_id_tx,
+                            &stash_changes_tx,
+                        )
+                        .await
+                    {
+                        // The client only retries until the headers, a body cut short is fetched
+                        // again here
+                        Err(e) if is_cut_short(&e) => {
+                            let delay = retry::backoff(round);
+                            warn!(
+                                "Page {} was cut short, fetching it again in {}: {:#}",
+                                change_id,
+                                delay.human_duration(),
+                                e
+                            );
+                            tokio::select! {
+                                _ = tokio::time::sleep(delay) => continue,
+                                _ = self.shutdown_token.cancelled() => break,
+                            }
+                        }
+                        result => return result,
+                    }
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    if status == reqwest::StatusCode::UNAUTHORIZED {
+                        self.health.set_token_valid(false);
+                    }
+                    // Rate limits that outlasted the middleware's retries clear up on their own too
+                    let retryable = retry::is_retryable_status(status)
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    (format!("HTTP {status}"), retryable)
+                }
+                Err(e) => (format!("{e:#}"), true),
+            };
 
-        if response.status() != reqwest::StatusCode::OK {
-            let status = response.status();
-            error!("Failed to fetch public stashes: HTTP {}", status);
+            error!("Failed to fetch change ID {}: {}", change_id, failure);
+            // Logged once, when the client first runs out of attempts
+            if !std::mem::replace(&mut failure_logged, true)
+                && let Some(dead_letter_dir) = &self.dead_letter_dir
+                && let Err(e) =
+                    dead_letter::record_fetch_failure(dead_letter_dir, &change_id, &failure).await
+            {
+                error!("Failed to dead-letter change ID {}: {:#}", change_id, e);
+            }
 
-            // Retry
-            info!("Retrying with change ID: {}", change_id);
-            if next_change_id_tx.send(change_id).is_err() {
-                debug!("Failed to send next change ID, receiver dropped");
-                return Ok(());
+            if !retryable {
+                // Retrying would fail the same way, stop the realm's chain until it is looked into
+                return Err(anyhow::anyhow!(
+                    "Failed to fetch public stashes: {}, not retrying",
+                    failure
+                ));
             }
 
-            return Err(anyhow::anyhow!(
-                "Failed to fetch public stashes: HTTP {}",
-                status
-            ));
+            info!(
+                "Retrying change ID {} in {} (round {})",
+                change_id,
+                retry::MAX_DELAY.human_duration(),
+                round + 1
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(retry::MAX_DELAY) => {},
+                _ = self.shutdown_token.cancelled() => break,
+            }
         }
 
+        debug!("Gave up on change ID {}, shutting down", change_id);
+        Ok(())
+    }
+
+    /// Sends the next change ID of a fetched page, unless it was already sent, then decodes the
+    /// page for the processor
+    async fn handle_page(
+        &self,
+        client: &reqwest_middleware::ClientWithMiddleware,
+        response: reqwest::Response,
+        sequence: u64,
+        change_id: &str,
+        next_change_id_tx: &mut Option<mpsc::Sender<(u64, String)>>,
+        stash_changes_tx: &mpsc::Sender<StashPage>,
+    ) -> Result<()> {
+        metrics::counter!(telemetry::PAGES_FETCHED, "realm" => self.realm.to_string()).increment(1);
+        let url = response.url().to_string();
+
         // Extract and send the next change ID as soon as headers are available
         let next_change_id = response
             .headers()
@@ -70,104 +293,176 @@ impl PublicStashWorker {
             .to_owned();
 
         // Send the next change ID immediately
-        if next_change_id_tx.send(next_change_id).is_err() {
+        if let Some(next_change_id_tx) = next_change_id_tx.take()
+            && next_change_id_tx
+                .send((sequence + 1, next_change_id.clone()))
+                .await
+                .is_err()
+        {
             debug!("Next change ID receiver dropped");
             return Ok(());
         }
 
-        // Now get the body and parse JSON
-        let mut compressed_bytes = 0u32;
-
-        // Get the response as a byte stream
-        let mut bytes_stream = response.bytes_stream();
-        let mut compressed_data = Vec::new();
-
-        // Collect all compressed bytes while counting them
-        while let Some(chunk) = bytes_stream.next().await {
-            let chunk = chunk.with_context(|| format!("Failed to read chunk from {url}"))?;
-            compressed_bytes += chunk.len() as u32;
-            compressed_data.extend_from_slice(&chunk);
+        // Queue the page for the processor before decoding it, waiting for room in the queue
+        let (page, stashes_tx, summary_tx) = StashPage::new(sequence, change_id.to_string());
+        if stash_changes_tx.send(page).await.is_err() {
+            debug!("Stash changes receiver dropped");
+            return Ok(());
         }
 
-        // Create a stream reader from the compressed data
-        let compressed_reader = std::io::Cursor::new(compressed_data);
-        let buf_reader = tokio::io::BufReader::new(compressed_reader);
+        // Keep the compressed chunks around only when they need to be archived, a page that fails
+        // to parse is fetched again to be dead-lettered
+        let archived_chunks = self
+            .archive_dir
+            .is_some()
+            .then(|| Arc::new(Mutex::new(Vec::new())));
 
-        // Decompress using gzip
-        let mut gzip_decoder = GzipDecoder::new(buf_reader);
-        let mut decompressed_data = Vec::new();
+        let bytes_stream = response
+            .bytes_stream()
+            .map_err(std::io::Error::other)
+            .inspect_ok({
+                let archived_chunks = archived_chunks.clone();
+                move |chunk: &Bytes| {
+                    if let Some(archived_chunks) = &archived_chunks {
+                        archived_chunks.lock().unwrap().push(chunk.clone());
+                    }
+                }
+            });
 
-        // Read all decompressed data
-        gzip_decoder
-            .read_to_end(&mut decompressed_data)
-            .instrument(tracing::trace_span!("decompress_gzip"))
-            .await
-            .with_context(|| format!("Failed to decompress gzip response from {url}"))?;
+        let summary = match page_decoder::decode_page(
+            bytes_stream,
+            stashes_tx,
+            self.schema_drift.clone(),
+            &url,
+        )
+        .await
+        {
+            Ok(summary) => summary,
+            // The partially decoded page is discarded by the processor, it is fetched again
+            Err(e) if is_cut_short(&e) => return Err(e),
+            Err(e) => {
+                let Some(decode_error) = e.downcast_ref::<DecodeError>() else {
+                    return Err(e);
+                };
+                metrics::counter!(telemetry::PARSE_FAILURES, "realm" => self.realm.to_string())
+                    .increment(1);
+                error!("Failed to parse page {}: {:#}", change_id, e);
 
-        let decompressed_bytes = decompressed_data.len() as u32;
+                let dead_lettered = match &self.dead_letter_dir {
+                    Some(dead_letter_dir) => {
+                        dead_letter_page(
+                            dead_letter_dir,
+                            client,
+                            &url,
+                            change_id,
+                            decode_error,
+                            archived_chunks,
+                        )
+                        .await
+                    }
+                    None => Err(anyhow::anyhow!("No dead-letter directory configured")),
+                };
+                if let Err(dead_letter_error) = dead_lettered {
+                    // Skipping the page would lose it, the crawl resumes before it on restart
+                    self.shutdown_token.cancel();
+                    return Err(dead_letter_error.context(format!(
+                        "Failed to dead-letter page {change_id}, stopping the crawler"
+                    )));
+                }
 
-        // Convert decompressed bytes to string
-        let text_body = String::from_utf8(decompressed_data)
-            .with_context(|| format!("Failed to convert decompressed data to UTF-8 from {url}"))?;
+                // The page is set aside to be retried, don't hold back the checkpoint for it
+                self.progress
+                    .lock()
+                    .unwrap()
+                    .complete(sequence, next_change_id);
+                return Ok(());
+            }
+        };
 
-        let stash_changes = serde_json::from_str::<PublicStashTabs>(&text_body)
-            .map_err(|e| {
-                debug!("Failed to parse JSON from {url}. Error: {e}");
+        if let (Some(archive_dir), Some(archived_chunks)) = (&self.archive_dir, archived_chunks) {
+            let compressed_data = archived_chunks.lock().unwrap().concat();
+            if let Err(e) = archive::write_page(archive_dir, change_id, &compressed_data).await {
+                error!("Failed to archive page: {:#}", e);
+            }
+        }
 
-                // Find the exact position where parsing failed
-                let line = e.line();
-                let column = e.column();
+        if summary_tx.send(summary).is_err() {
+            debug!("Stash changes receiver dropped");
+        }
 
-                // Calculate byte offset from line/column
-                let mut byte_offset = 0;
-                let mut current_line = 1;
-                let mut current_column = 1;
+        Ok(())
+    }
 
-                for (i, ch) in text_body.char_indices() {
-                    if current_line == line && current_column == column {
-                        byte_offset = i;
-                        break;
-                    }
-                    if ch == '\n' {
-                        current_line += 1;
-                        current_column = 1;
-                    } else {
-                        current_column += 1;
-                    }
-                }
+    /// Replays compressed pages through the stash processor, in the given order, returning the
+    /// change IDs of the pages that were decoded, each with a receiver resolving once the page is
+    /// inserted
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn replay(
+        self: Arc<Self>,
+        pages: Vec<(String, PathBuf)>,
+        stash_changes_tx: mpsc::Sender<StashPage>,
+    ) -> Result<Vec<(String, oneshot::Receiver<()>)>> {
+        let mut replayed = Vec::with_capacity(pages.len());
 
-                // Extract 100 characters before and after the error position
-                let start = byte_offset.saturating_sub(100);
-                let end = (byte_offset + 100).min(text_body.len());
-                let context = &text_body[start..end];
+        for (sequence, (change_id, path)) in pages.into_iter().enumerate() {
+            if self.shutdown_token.is_cancelled() {
+                debug!("Shutting down replay");
+                break;
+            }
 
-                debug!("Parse error at line {line}, column {column}");
-                debug!("Context (100 chars before/after): {}", context);
+            let file = tokio::fs::File::open(&path)
+                .await
+                .with_context(|| format!("Failed to open page {}", path.display()))?;
 
-                e
-            })
-            .with_context(|| format!("Failed to parse response body from {url}"))?;
+            let (mut page, stashes_tx, summary_tx) =
+                StashPage::new(sequence as u64, change_id.clone());
+            let (inserted_tx, inserted_rx) = oneshot::channel();
+            page.inserted = Some(inserted_tx);
+            if stash_changes_tx.send(page).await.is_err() {
+                debug!("Stash changes receiver dropped");
+                break;
+            }
 
-        // Send the parsed stash data along with compressed byte count
-        if stash_changes_tx
-            .send((stash_changes, compressed_bytes, decompressed_bytes))
-            .is_err()
-        {
-            debug!("Stash changes receiver dropped");
+            match page_decoder::decode_page(
+                ReaderStream::new(file),
+                stashes_tx,
+                self.schema_drift.clone(),
+                &path.display().to_string(),
+            )
+            .await
+            {
+                Ok(summary) => {
+                    let _ = summary_tx.send(summary);
+                    replayed.push((change_id, inserted_rx));
+                }
+                Err(e) => {
+                    metrics::counter!(telemetry::PARSE_FAILURES, "realm" => self.realm.to_string())
+                        .increment(1);
+                    error!("Skipping page {}: {:#}", change_id, e);
+                }
+            }
         }
 
-        Ok(())
+        Ok(replayed)
     }
 
-    /// Processes stash changes from the queue and updates statistics
-    #[tracing::instrument(skip_all, level = "trace")]
+    /// Processes stash changes from the queue and updates statistics.
+    ///
+    /// Fails once the rows of a page can't be inserted, after cancelling the crawl: skipping the
+    /// page would hold the checkpoint back for good.
+    #[tracing::instrument(skip_all, level = "trace", fields(realm = %self.realm))]
     pub async fn process_stash(
         self: Arc<Self>,
-        mut stash_changes_rx: mpsc::UnboundedReceiver<(PublicStashTabs, u32, u32)>,
+        mut stash_changes_rx: mpsc::Receiver<StashPage>,
         db: db::Client,
-    ) {
-        while let Some((stash_changes, compressed_bytes, decompressed_bytes)) =
-            stash_changes_rx.recv().await
+    ) -> Result<()> {
+        while let Some(StashPage {
+            sequence,
+            change_id,
+            mut stashes,
+            summary,
+            inserted,
+        }) = stash_changes_rx.recv().await
         {
             if self.shutdown_token.is_cancelled() {
                 debug!("Shutting down stash processor");
@@ -177,24 +472,43 @@ impl PublicStashWorker {
             let start_time = std::time::Instant::now();
             let timestamp = Utc::now();
 
+            let realm = self.realm.to_string();
+            metrics::gauge!(telemetry::PROCESSING_QUEUE_DEPTH, "realm" => realm.clone())
+                .set(stash_changes_rx.len() as f64);
             let mut items = Vec::new();
-            for stash in stash_changes.stashes.iter() {
-                let stash_price = extract_price(stash.stash.as_ref());
+            let mut listings = Vec::new();
+            let mut stash_contents = Vec::new();
+            let mut stash_count = 0u32;
+            let mut item_count = 0u32;
+            let mut unmatched_mods = 0u32;
+            let mut unknown_currencies = 0u32;
+
+            while let Some(stash) = stashes.recv().await {
+                stash_count += 1;
+                item_count += stash.items.len() as u32;
+
+                let stash_price = stash
+                    .stash
+                    .as_deref()
+                    .and_then(|note| price::parse_note(note, &self.currencies));
+                let mut listed_items = HashMap::with_capacity(stash.items.len());
 
                 for item in stash.items.iter() {
+                    let item_price = item
+                        .note
+                        .as_deref()
+                        .and_then(|note| price::parse_note(note, &self.currencies));
+                    let final_price = item_price.or_else(|| stash_price.clone());
+                    listed_items.insert(item.id.clone(), final_price.clone());
+
+                    let Some(final_price) = final_price else {
+                        continue;
+                    };
+
                     let league = item.league.clone();
                     let (level, quality) = extract_gem_properties(item);
                     let influences = extract_influences(item);
                     let (passives, tier) = extract_passives_and_tier(item);
-                    let item_price = extract_price(item.note.as_ref());
-
-                    let final_price = if let Some(item_price) = item_price {
-                        item_price
-                    } else if let Some(stash_price_ref) = stash_price.as_ref() {
-                        stash_price_ref.clone()
-                    } else {
-                        continue;
-                    };
 
                     let is_unique = item.frame_type == 3;
                     let name = if is_unique {
@@ -203,10 +517,40 @@ impl PublicStashWorker {
                         String::new()
                     };
                     let links = count_links(item);
+                    let stats = self.stat_templates.parse_item(item);
+                    unmatched_mods += stats.unmatched;
+                    let currency_known = final_price.currency.is_known();
+                    if !currency_known {
+                        unknown_currencies += 1;
+                    }
+                    let pseudo = PseudoStats::compute(item, &stats);
+                    let price = (
+                        timestamp.timestamp() as u32,
+                        final_price.quantity,
+                        final_price.currency.to_string(),
+                    );
+
+                    listings.push(ItemListing {
+                        realm: realm.clone(),
+                        league: league.clone(),
+                        item_id: item.id.clone(),
+                        base: item.base_type.clone(),
+                        name: name.clone(),
+                        frame_type: item.frame_type,
+                        first_seen: timestamp,
+                        last_seen: timestamp,
+                        removed_at: DateTime::<Utc>::UNIX_EPOCH,
+                        price_changes: 0,
+                        first_price: price.clone(),
+                        last_price: price,
+                    });
 
                     items.push(db::Item {
                         timestamp,
+                        realm: realm.clone(),
                         league,
+                        stash_id: stash.id.clone(),
+                        item_id: item.id.clone(),
                         base: item.base_type.clone(),
                         name,
                         links,
@@ -219,14 +563,66 @@ impl PublicStashWorker {
                         passives,
                         tier,
                         influences,
+                        enchant_mods: item.enchant_mods.clone().unwrap_or_default(),
+                        implicit_mods: item.implicit_mods.clone().unwrap_or_default(),
+                        explicit_mods: item.explicit_mods.clone().unwrap_or_default(),
+                        crafted_mods: item.crafted_mods.clone().unwrap_or_default(),
+                        fractured_mods: item.fractured_mods.clone().unwrap_or_default(),
+                        veiled_mods: item.veiled_mods.clone().unwrap_or_default(),
+                        scourge_mods: item.scourge_mods.clone().unwrap_or_default(),
+                        crucible_mods: item.crucible_mods.clone().unwrap_or_default(),
+                        utility_mods: item.utility_mods.clone().unwrap_or_default(),
+                        rune_mods: item.rune_mods.clone().unwrap_or_default(),
+                        stat_ids: stats.stat_ids,
+                        stat_values: stats.stat_values,
+                        pseudo_total_life: pseudo.total_life,
+                        pseudo_total_mana: pseudo.total_mana,
+                        pseudo_total_energy_shield: pseudo.total_energy_shield,
+                        pseudo_total_elemental_resistance: pseudo.total_elemental_resistance,
+                        pseudo_total_chaos_resistance: pseudo.total_chaos_resistance,
+                        pseudo_total_strength: pseudo.total_strength,
+                        pseudo_total_dexterity: pseudo.total_dexterity,
+                        pseudo_total_intelligence: pseudo.total_intelligence,
+                        pseudo_open_prefixes: pseudo.open_prefixes,
+                        pseudo_open_suffixes: pseudo.open_suffixes,
+                        listing_type: final_price.listing_type,
                         price_quantity: final_price.quantity,
                         price_currency: final_price.currency.to_string(),
+                        price_currency_known: currency_known,
                     });
                 }
+
+                stash_contents.push(StashContents {
+                    stash_id: stash.id,
+                    league: stash.league,
+                    items: listed_items,
+                });
             }
 
+            // Only commit pages that were fully decoded, the fetcher reports the failure otherwise
+            let Ok(PageSummary {
+                next_change_id,
+                compressed_bytes,
+                decompressed_bytes,
+            }) = summary.await
+            else {
+                debug!("Discarding partially decoded page {}", sequence);
+                continue;
+            };
+
             let end_time = std::time::Instant::now();
 
+            let labels = [("realm", realm.clone())];
+            metrics::counter!(telemetry::COMPRESSED_BYTES, &labels)
+                .increment(compressed_bytes.into());
+            metrics::counter!(telemetry::DECOMPRESSED_BYTES, &labels)
+                .increment(decompressed_bytes.into());
+            metrics::counter!(telemetry::ITEMS_PARSED, &labels).increment(item_count.into());
+            metrics::counter!(telemetry::ITEMS_PRICED, &labels).increment(items.len() as u64);
+            metrics::counter!(telemetry::MODS_UNMATCHED, &labels).increment(unmatched_mods.into());
+            metrics::counter!(telemetry::UNKNOWN_CURRENCIES, &labels)
+                .increment(unknown_currencies.into());
+
             if !items.is_empty() {
                 debug!(
                     "Processed {} items in {} ({}, {}/item)",
@@ -238,29 +634,155 @@ impl PublicStashWorker {
                 );
             }
 
-            if let Err(e) = db.insert_items(items).await {
-                error!("Failed to insert items: {}", e);
+            // Stashes evicted from memory, or seen before a restart, are diffed against the listings
+            // stored for them
+            let missing = self.snapshots.lock().unwrap().missing(&stash_contents);
+            if !missing.is_empty() {
+                metrics::counter!(telemetry::STASH_SNAPSHOTS_RESTORED, &labels)
+                    .increment(missing.len() as u64);
+                match db.current_stash_listings(&realm, &missing).await {
+                    Ok(listings) => self.snapshots.lock().unwrap().restore(listings),
+                    Err(_) if self.shutdown_token.is_cancelled() => {
+                        debug!("Shutting down stash processor");
+                        break;
+                    }
+                    Err(e) => {
+                        // Diffing without them would report every item of the stashes as added
+                        self.shutdown_token.cancel();
+                        return Err(anyhow::Error::new(e).context(format!(
+                            "Failed to restore the stashes of page {sequence}, stopping the crawler"
+                        )));
+                    }
+                }
             }
 
-            let stash_count = stash_changes.stashes.len() as u32;
-            let item_count: u32 = stash_changes
-                .stashes
+            let changes = {
+                let snapshots = self.snapshots.lock().unwrap();
+                stash_contents
+                    .iter()
+                    .flat_map(|contents| snapshots.diff(contents))
+                    .collect::<Vec<_>>()
+            };
+
+            // Fold the changes into the lifecycle of the listings they affect
+            let repriced = changes
                 .iter()
-                .map(|stash| stash.items.len() as u32)
-                .sum();
+                .filter(|change| change.kind == StashEventKind::Repriced)
+                .map(|change| change.item_id.as_str())
+                .collect::<HashSet<_>>();
+            for listing in listings.iter_mut() {
+                if repriced.contains(listing.item_id.as_str()) {
+                    listing.price_changes = 1;
+                }
+            }
+            for change in changes.iter() {
+                let (StashEventKind::Removed, Some(previous_price)) =
+                    (change.kind, &change.previous_price)
+                else {
+                    continue;
+                };
+
+                listings.push(ItemListing {
+                    realm: realm.clone(),
+                    league: change.league.clone(),
+                    item_id: change.item_id.clone(),
+                    base: String::new(),
+                    name: String::new(),
+                    frame_type: 0,
+                    first_seen: timestamp,
+                    last_seen: DateTime::<Utc>::UNIX_EPOCH,
+                    removed_at: timestamp,
+                    price_changes: 0,
+                    first_price: (
+                        timestamp.timestamp() as u32,
+                        previous_price.quantity,
+                        previous_price.currency.to_string(),
+                    ),
+                    last_price: (0, 0.0, String::new()),
+                });
+            }
+
+            let stash_events = changes
+                .into_iter()
+                .map(|change| {
+                    let (price_quantity, price_currency) = price_columns(change.price);
+                    let (previous_price_quantity, previous_price_currency) =
+                        price_columns(change.previous_price);
+
+                    StashEvent {
+                        timestamp,
+                        realm: realm.clone(),
+                        league: change.league,
+                        stash_id: change.stash_id,
+                        item_id: change.item_id,
+                        kind: change.kind,
+                        price_quantity,
+                        price_currency,
+                        previous_price_quantity,
+                        previous_price_currency,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            // The server drops the tables' batches it already has when a page is inserted again
+            // after a failure or a restart. The items go in last: they feed the current listings
+            // the stash snapshots are restored from, so the events of a page are never lost to a
+            // restored snapshot that already holds its items
+            let token = format!("{realm}/{change_id}");
+            let inserted_rows = async {
+                self.insert_with_retries("stash_events", || {
+                    db.insert_stash_events(&stash_events, &token)
+                })
+                .await?;
+                self.insert_with_retries("item_listings", || {
+                    db.insert_item_listings(&listings, &token)
+                })
+                .await?;
+                self.insert_with_retries("items", || db.insert_items(&items, &token))
+                    .await
+            }
+            .await;
+            if let Err(e) = inserted_rows {
+                if self.shutdown_token.is_cancelled() {
+                    debug!("Shutting down stash processor");
+                    break;
+                }
+                // The crawl resumes from the last checkpoint once the database is back
+                self.shutdown_token.cancel();
+                return Err(e.context(format!(
+                    "Failed to insert page {sequence}, stopping the crawler"
+                )));
+            }
+            self.health.page_ingested(&realm);
+            if let Some(inserted) = inserted {
+                let _ = inserted.send(());
+            }
+
+            // The snapshots only move on once their changes are stored, a page that failed to
+            // insert is diffed again
+            {
+                let mut snapshots = self.snapshots.lock().unwrap();
+                for contents in stash_contents {
+                    snapshots.commit(contents);
+                }
+                metrics::gauge!(telemetry::STASH_SNAPSHOTS, &labels).set(snapshots.len() as f64);
+            }
 
             debug!(
-                "Processed batch: {} stashes / {} items / {}/{} bytes ({:.1}:1 ratio)",
+                "Processed batch: {} stashes / {} items / {}/{} bytes ({:.1}:1 ratio) / queue {}/{}",
                 stash_count.human_count_bare(),
                 item_count.human_count_bare(),
                 compressed_bytes.human_count_bytes(),
                 decompressed_bytes.human_count_bytes(),
                 decompressed_bytes as f64 / compressed_bytes as f64,
+                stash_changes_rx.len(),
+                stash_changes_rx.max_capacity(),
             );
 
             if let Err(e) = db
                 .insert_statistics_event(StatisticsEvent {
                     timestamp: Utc::now(),
+                    realm: realm.clone(),
                     stash_count,
                     item_count,
                     compressed_bytes,
@@ -270,10 +792,113 @@ impl PublicStashWorker {
             {
                 error!("Failed to insert statistics event: {}", e);
             }
+
+            if !self.checkpoints {
+                continue;
+            }
+
+            let resume_change_id = self
+                .progress
+                .lock()
+                .unwrap()
+                .complete(sequence, next_change_id);
+
+            if let Some(change_id) = resume_change_id
+                && let Err(e) = db
+                    .insert_checkpoint(Checkpoint {
+                        timestamp: Utc::now(),
+                        realm,
+                        change_id,
+                    })
+                    .await
+            {
+                error!("Failed to insert checkpoint: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs an insert until it goes through, with jittered exponential backoff between attempts
+    async fn insert_with_retries<F, Fut, E>(&self, table: &str, insert: F) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match insert().await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= INSERT_ATTEMPTS => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to insert into {table} after {attempt} attempts")
+                    });
+                }
+                Err(e) => e,
+            };
+
+            let delay = retry::backoff(attempt);
+            warn!(
+                "Failed to insert into {} (attempt {}/{}), retrying in {}: {}",
+                table,
+                attempt,
+                INSERT_ATTEMPTS,
+                delay.human_duration(),
+                error
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = self.shutdown_token.cancelled() => {
+                    return Err(error).context("Insert interrupted by shutdown");
+                }
+            }
+            attempt += 1;
         }
     }
 }
 
+/// Saves a page that could not be parsed to the dead-letter directory, to be replayed once the
+/// stash types are fixed.
+///
+/// The compressed page is taken from the archived chunks when there are some, and fetched again
+/// from `url` otherwise.
+async fn dead_letter_page(
+    directory: &Path,
+    client: &reqwest_middleware::ClientWithMiddleware,
+    url: &str,
+    change_id: &str,
+    decode_error: &DecodeError,
+    archived_chunks: Option<Arc<Mutex<Vec<Bytes>>>>,
+) -> Result<()> {
+    let compressed_data = match archived_chunks {
+        Some(archived_chunks) => Bytes::from(archived_chunks.lock().unwrap().concat()),
+        None => client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .with_context(|| format!("Failed to fetch page {change_id} again"))?,
+    };
+    dead_letter::record_page(
+        directory,
+        change_id,
+        &decode_error.path,
+        &decode_error.error.to_string(),
+        &compressed_data,
+    )
+    .await
+}
+
+/// Whether a page failed because its body was cut short, rather than because of its contents
+fn is_cut_short(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<DecodeError>()
+        .is_some_and(DecodeError::is_io)
+}
+
 /// Extract gem level and quality from item properties
 fn extract_gem_properties(item: &crate::poe::types::Item) -> (u8, u8) {
     let mut level = 0u8;
@@ -283,31 +908,31 @@ fn extract_gem_properties(item: &crate::poe::types::Item) -> (u8, u8) {
         for prop in properties {
             match prop.name.as_str() {
                 "Level" => {
-                    if let Some(values_vec) = prop.values.first() {
-                        if let Some(value) = values_vec.first() {
-                            match value {
-                                crate::poe::types::Value::String(s) => {
-                                    level = s.parse().unwrap_or(0).min(255) as u8;
-                                }
-                                crate::poe::types::Value::Integer(i) => {
-                                    level = (*i).max(0).min(255) as u8;
-                                }
+                    if let Some(values_vec) = prop.values.first()
+                        && let Some(value) = values_vec.first()
+                    {
+                        match value {
+                            crate::poe::types::Value::String(s) => {
+                                level = s.parse().unwrap_or(0).min(255) as u8;
+                            }
+                            crate::poe::types::Value::Integer(i) => {
+                                level = (*i).clamp(0, 255) as u8;
                             }
                         }
                     }
                 }
                 "Quality" => {
-                    if let Some(values_vec) = prop.values.first() {
-                        if let Some(value) = values_vec.first() {
-                            match value {
-                                crate::poe::types::Value::String(s) => {
-                                    // Remove the % symbol if present
-                                    let clean_value = s.trim_end_matches('%');
-                                    quality = clean_value.parse().unwrap_or(0).min(255) as u8;
-                                }
-                                crate::poe::types::Value::Integer(i) => {
-                                    quality = (*i).max(0).min(255) as u8;
-                                }
+                    if let Some(values_vec) = prop.values.first()
+                        && let Some(value) = values_vec.first()
+                    {
+                        match value {
+                            crate::poe::types::Value::String(s) => {
+                                // Remove the % symbol if present
+                                let clean_value = s.trim_end_matches('%');
+                                quality = clean_value.parse().unwrap_or(0).min(255) as u8;
+                            }
+                            crate::poe::types::Value::Integer(i) => {
+                                quality = (*i).clamp(0, 255) as u8;
                             }
                         }
                     }
@@ -359,29 +984,29 @@ fn extract_passives_and_tier(item: &crate::poe::types::Item) -> (u8, u8) {
         for prop in properties {
             match prop.name.as_str() {
                 "Added Small Passive Skills" | "Added Passives" => {
-                    if let Some(values_vec) = prop.values.first() {
-                        if let Some(value) = values_vec.first() {
-                            match value {
-                                crate::poe::types::Value::String(s) => {
-                                    passives = s.parse().unwrap_or(0).min(255) as u8;
-                                }
-                                crate::poe::types::Value::Integer(i) => {
-                                    passives = (*i).max(0).min(255) as u8;
-                                }
+                    if let Some(values_vec) = prop.values.first()
+                        && let Some(value) = values_vec.first()
+                    {
+                        match value {
+                            crate::poe::types::Value::String(s) => {
+                                passives = s.parse().unwrap_or(0).min(255) as u8;
+                            }
+                            crate::poe::types::Value::Integer(i) => {
+                                passives = (*i).clamp(0, 255) as u8;
                             }
                         }
                     }
                 }
                 "Map Tier" | "Tier" => {
-                    if let Some(values_vec) = prop.values.first() {
-                        if let Some(value) = values_vec.first() {
-                            match value {
-                                crate::poe::types::Value::String(s) => {
-                                    tier = s.parse().unwrap_or(0).min(255) as u8;
-                                }
-                                crate::poe::types::Value::Integer(i) => {
-                                    tier = (*i).max(0).min(255) as u8;
-                                }
+                    if let Some(values_vec) = prop.values.first()
+                        && let Some(value) = values_vec.first()
+                    {
+                        match value {
+                            crate::poe::types::Value::String(s) => {
+                                tier = s.parse().unwrap_or(0).min(255) as u8;
+                            }
+                            crate::poe::types::Value::Integer(i) => {
+                                tier = (*i).clamp(0, 255) as u8;
                             }
                         }
                     }
@@ -438,38 +1063,9 @@ fn count_links(item: &crate::poe::types::Item) -> u8 {
     max_link_group
 }
 
-#[derive(Clone)]
-struct ListingPrice {
-    quantity: f32,
-    currency: ListingCurrency,
-}
-
-fn extract_price(note: Option<&String>) -> Option<ListingPrice> {
-    let note = note?;
-
-    type InputError<T> = winnow::error::InputError<T>;
-
-    // The parser is defined as a sequence of smaller parsers using a tuple.
-    // This is an idiomatic way to define a sequence in `winnow`.
-    // 1. Prefix: `~price` or `~b/o`
-    // 2. Whitespace: one or more space characters.
-    // 3. Amount: a decimal unsigned 32-bit integer.
-    // 4. Whitespace: one or more space characters.
-    // 5. Currency Name: a string of alphabetic characters and hyphens.
-    let mut parser = (
-        alt(("~price", "~b/o")),
-        multispace1::<_, InputError<_>>,
-        winnow::ascii::float::<_, f32, InputError<_>>,
-        multispace1::<_, InputError<_>>,
-        take_while(1.., |c: char| c.is_alphabetic() || c == '-'),
-    );
-
-    let (_, _, amount, _, currency_str) = parser.parse_next(&mut note.as_str()).ok()?;
-
-    let currency = ListingCurrency::from_str(currency_str).ok()?;
-
-    Some(ListingPrice {
-        quantity: amount,
-        currency,
+/// Splits an optional price into its quantity and currency columns
+fn price_columns(price: Option<ListingPrice>) -> (f32, String) {
+    price.map_or((0.0, String::new()), |price| {
+        (price.quantity, price.currency.to_string())
     })
 }
@@ -0,0 +1,418 @@
+mod pacing;
+mod store;
+
+pub use store::{MemoryStore, RateLimitStore, RedisStore, RuleState};
+
+use crate::telemetry;
+use async_trait::async_trait;
+use http::HeaderMap;
+use human_repr::HumanDuration;
+use pacing::{Pacer, RuleLimit};
+use pashe_config::RateLimitPacingConfig;
+use reqwest::{Response, StatusCode};
+use reqwest_middleware::{Middleware, Next};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use store::now_ms;
+use tokio_util::sync::CancellationToken;
+
+// Custom middleware for handling the specific rate limiting logic
+#[derive(Debug)]
+pub struct RateLimitMiddleware {
+    store: Arc<dyn RateLimitStore>,
+    /// Policy each endpoint falls under, as learnt from the `X-Rate-Limit-Policy` header
+    policies: Mutex<HashMap<String, String>>,
+    /// Spreads requests over the rate limit windows, unless they are sent in bursts
+    pacer: Option<Mutex<Pacer>>,
+    shutdown_token: CancellationToken,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(
+        shutdown_token: CancellationToken,
+        store: Arc<dyn RateLimitStore>,
+        pacing: RateLimitPacingConfig,
+    ) -> Self {
+        Self {
+            store,
+            policies: Mutex::default(),
+            pacer: (pacing == RateLimitPacingConfig::Smooth).then(Mutex::default),
+            shutdown_token,
+        }
+    }
+
+    /// Waits until every rule of the policy has a hit left and reserves it, then waits for
+    /// the request's turn when pacing
+    async fn acquire(&self, policy: &str) -> reqwest_middleware::Result<()> {
+        loop {
+            let wait_duration = match self.store.acquire(policy, now_ms()).await {
+                Ok(wait_ms) => Duration::from_millis(wait_ms),
+                Err(e) => {
+                    // Rely on the reactive handling of 429s until the store is back
+                    tracing::warn!("Failed to acquire from the rate limit store: {:#}", e);
+                    return Ok(());
+                }
+            };
+
+            if wait_duration.is_zero() {
+                break;
+            }
+
+            tracing::warn!(
+                "Proactive rate limit on {}: waiting for {}",
+                policy,
+                wait_duration.human_duration()
+            );
+            self.sleep(wait_duration).await?;
+        }
+
+        if let Some(pacer) = &self.pacer {
+            let wait_ms = pacer.lock().unwrap().schedule(policy, now_ms());
+            tracing::trace!("Pacing {}: waiting for {}ms", policy, wait_ms);
+            self.sleep(Duration::from_millis(wait_ms)).await?;
+        }
+        Ok(())
+    }
+
+    /// Sleeps for a proactive rate limit, unless the crawler shuts down
+    async fn sleep(&self, duration: Duration) -> reqwest_middleware::Result<()> {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => Ok(()),
+            _ = self.shutdown_token.cancelled() => {
+                tracing::info!("Proactive rate limit sleep interrupted by shutdown");
+                Err(reqwest_middleware::Error::Middleware(anyhow::anyhow!(
+                    "Rate limit sleep interrupted by shutdown"
+                )))
+            }
+        }
+    }
+
+    /// Updates the state of the policy from the headers of a response to a request sent at
+    /// `request_time_ms`
+    async fn record(&self, path: &str, request_time_ms: u64, headers: &HeaderMap) {
+        let Some(PolicyState {
+            policy,
+            rules,
+            limits,
+        }) = parse_rules(headers, request_time_ms)
+        else {
+            return;
+        };
+
+        if let Some(pacer) = &self.pacer {
+            pacer.lock().unwrap().set_limits(&policy, &limits);
+        }
+        self.policies
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), policy.clone());
+        if let Err(e) = self.store.update(&policy, rules).await {
+            tracing::warn!("Failed to update the rate limit store: {:#}", e);
+        }
+    }
+}
+
+impl Default for RateLimitMiddleware {
+    fn default() -> Self {
+        Self::new(
+            CancellationToken::new(),
+            Arc::new(MemoryStore::default()),
+            RateLimitPacingConfig::default(),
+        )
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimitMiddleware {
+    #[tracing::instrument(skip_all, level = "trace")]
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let path = req.url().path().to_string();
+        let mut retries = 3; // Max retries
+        let mut res;
+
+        loop {
+            // We need to clone the request, in case we need to retry it.
+            // This will fail if the request body is a stream.
+            let req_clone = req.try_clone().ok_or_else(|| {
+                reqwest_middleware::Error::Middleware(anyhow::anyhow!(
+                    "Request body is not cloneable, cannot retry"
+                ))
+            })?;
+
+            // Proactive check: Wait if we know we are near the limit of the endpoint's policy
+            let policy = self.policies.lock().unwrap().get(&path).cloned();
+            if let Some(policy) = &policy {
+                self.acquire(policy).await?;
+            }
+
+            let request_time_ms = now_ms();
+            res = next.clone().run(req_clone, extensions).await?;
+            self.record(&path, request_time_ms, res.headers()).await;
+
+            // Reactive check: handle 429
+            if res.status() == StatusCode::TOO_MANY_REQUESTS && retries > 0 {
+                retries -= 1;
+                if let Some(retry_after) = res.headers().get("Retry-After")
+                    && let Ok(seconds) = retry_after.to_str().unwrap_or("5").parse::<u64>()
+                {
+                    let wait_duration = Duration::from_secs(seconds);
+                    tracing::warn!(
+                        "Reactive rate limit (429): waiting for {} before retrying. Retries left: {}",
+                        wait_duration.human_duration(),
+                        retries
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait_duration) => {},
+                        _ = self.shutdown_token.cancelled() => {
+                            tracing::info!("Reactive rate limit sleep interrupted by shutdown");
+                            return Err(reqwest_middleware::Error::Middleware(anyhow::anyhow!(
+                                "Rate limit sleep interrupted by shutdown"
+                            )));
+                        }
+                    }
+                    // Continue to the next iteration of the loop to retry
+                    continue;
+                }
+                // If Retry-After is not present, break and return the 429 response
+                break;
+            }
+
+            // If the status is not 429, or we are out of retries, break the loop
+            break;
+        }
+
+        Ok(res)
+    }
+}
+
+/// Rate limit rules reported in the headers of a response
+struct PolicyState {
+    policy: String,
+    rules: Vec<(String, RuleState)>,
+    limits: Vec<RuleLimit>,
+}
+
+/// Parses the policy and the state of each of its rules, keeping one hit of every rule in
+/// reserve so that the limit, and the ban that comes with exceeding it, is never reached
+fn parse_rules(headers: &HeaderMap, request_time_ms: u64) -> Option<PolicyState> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let policy = header("X-Rate-Limit-Policy")?.to_string();
+    let rules = header("X-Rate-Limit-Rules")?;
+
+    let mut states = Vec::new();
+    let mut limits = Vec::new();
+    for rule in rules.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let (Some(rule_limits), Some(rule_states)) = (
+            header(&format!("X-Rate-Limit-{rule}")),
+            header(&format!("X-Rate-Limit-{rule}-State")),
+        ) else {
+            continue;
+        };
+
+        // A rule can define several windows, "max_hits:period:ban_time" for the limits and
+        // "current_hits:period:active_ban" for their states, in the same order
+        for (index, (limit, state)) in rule_limits
+            .split(',')
+            .zip(rule_states.split(','))
+            .enumerate()
+        {
+            let limit: Vec<u64> = limit.split(':').filter_map(|s| s.parse().ok()).collect();
+            let state: Vec<u64> = state.split(':').filter_map(|s| s.parse().ok()).collect();
+            let ([max_hits, period_secs, ban_time], [current_hits, _, active_ban]) =
+                (limit.as_slice(), state.as_slice())
+            else {
+                continue;
+            };
+
+            limits.push(RuleLimit {
+                max_hits: *max_hits as u32,
+                period_ms: period_secs * 1000,
+            });
+
+            let remaining_hits = max_hits.saturating_sub(*current_hits) as u32;
+            metrics::gauge!(
+                telemetry::RATE_LIMIT_REMAINING_HITS,
+                "policy" => policy.clone(),
+                "rule" => rule.to_string(),
+                "period" => period_secs.to_string(),
+            )
+            .set(remaining_hits);
+
+            let new_state = if *active_ban > 0 {
+                tracing::warn!(
+                    "Banned by the {}/{} rate limit for {}",
+                    policy,
+                    rule,
+                    Duration::from_secs(*active_ban).human_duration()
+                );
+                RuleState {
+                    remaining_hits: 0,
+                    reset_at_ms: request_time_ms + active_ban * 1000,
+                }
+            } else {
+                if remaining_hits == 0 {
+                    tracing::warn!(
+                        "Exceeded the {}/{} rate limit, which bans for {}",
+                        policy,
+                        rule,
+                        Duration::from_secs(*ban_time).human_duration()
+                    );
+                }
+                RuleState {
+                    remaining_hits: remaining_hits.saturating_sub(1),
+                    reset_at_ms: request_time_ms + period_secs * 1000,
+                }
+            };
+            states.push((format!("{rule}:{index}"), new_state));
+        }
+    }
+
+    Some(PolicyState {
+        policy,
+        rules: states,
+        limits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    const REQUEST_TIME_MS: u64 = 1_000_000;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (*name, HeaderValue::from_static(value)))
+            .map(|(name, value)| (name.parse().unwrap(), value))
+            .collect()
+    }
+
+    fn state(remaining_hits: u32, reset_in_secs: u64) -> RuleState {
+        RuleState {
+            remaining_hits,
+            reset_at_ms: REQUEST_TIME_MS + reset_in_secs * 1000,
+        }
+    }
+
+    #[test]
+    fn parses_every_window_of_every_rule() {
+        let headers = headers(&[
+            ("X-Rate-Limit-Policy", "stash-request-limit"),
+            ("X-Rate-Limit-Rules", "Ip, Account"),
+            ("X-Rate-Limit-Ip", "45:60:120,240:240:900"),
+            ("X-Rate-Limit-Ip-State", "5:60:0,100:240:0"),
+            ("X-Rate-Limit-Account", "30:60:60"),
+            ("X-Rate-Limit-Account-State", "10:60:0"),
+        ]);
+
+        let PolicyState {
+            policy,
+            rules,
+            limits,
+        } = parse_rules(&headers, REQUEST_TIME_MS).unwrap();
+
+        assert_eq!(policy, "stash-request-limit");
+        assert_eq!(
+            rules,
+            [
+                ("Ip:0".to_string(), state(39, 60)),
+                ("Ip:1".to_string(), state(139, 240)),
+                ("Account:0".to_string(), state(19, 60)),
+            ]
+        );
+        assert_eq!(
+            limits,
+            [
+                RuleLimit {
+                    max_hits: 45,
+                    period_ms: 60_000
+                },
+                RuleLimit {
+                    max_hits: 240,
+                    period_ms: 240_000
+                },
+                RuleLimit {
+                    max_hits: 30,
+                    period_ms: 60_000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_rules_without_headers() {
+        let headers = headers(&[
+            ("X-Rate-Limit-Policy", "stash-request-limit"),
+            ("X-Rate-Limit-Rules", "Ip,Client"),
+            ("X-Rate-Limit-Ip", "45:60:120"),
+            ("X-Rate-Limit-Ip-State", "5:60:0"),
+            ("X-Rate-Limit-Client", "30:60:60"),
+        ]);
+
+        let PolicyState { rules, .. } = parse_rules(&headers, REQUEST_TIME_MS).unwrap();
+
+        assert_eq!(rules, [("Ip:0".to_string(), state(39, 60))]);
+        assert!(parse_rules(&HeaderMap::new(), REQUEST_TIME_MS).is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_hit_in_reserve() {
+        let headers = headers(&[
+            ("X-Rate-Limit-Policy", "stash-request-limit"),
+            ("X-Rate-Limit-Rules", "Ip"),
+            ("X-Rate-Limit-Ip", "10:60:120"),
+            ("X-Rate-Limit-Ip-State", "8:60:0"),
+        ]);
+        let PolicyState { policy, rules, .. } = parse_rules(&headers, REQUEST_TIME_MS).unwrap();
+        assert_eq!(rules, [("Ip:0".to_string(), state(1, 60))]);
+
+        let store = MemoryStore::default();
+        store.update(&policy, rules).await.unwrap();
+
+        // One more request goes out, the tenth hit is never spent
+        assert_eq!(store.acquire(&policy, REQUEST_TIME_MS).await.unwrap(), 0);
+        assert_eq!(
+            store.acquire(&policy, REQUEST_TIME_MS).await.unwrap(),
+            60_000
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_out_an_active_ban() {
+        let headers = headers(&[
+            ("X-Rate-Limit-Policy", "stash-request-limit"),
+            ("X-Rate-Limit-Rules", "Ip"),
+            ("X-Rate-Limit-Ip", "10:60:300"),
+            ("X-Rate-Limit-Ip-State", "11:60:300"),
+        ]);
+        let PolicyState { policy, rules, .. } = parse_rules(&headers, REQUEST_TIME_MS).unwrap();
+        // The ban outlasts the window, it runs from the request rather than the response
+        assert_eq!(rules, [("Ip:0".to_string(), state(0, 300))]);
+
+        let store = MemoryStore::default();
+        store.update(&policy, rules).await.unwrap();
+
+        assert_eq!(
+            store
+                .acquire(&policy, REQUEST_TIME_MS + 60_000)
+                .await
+                .unwrap(),
+            240_000
+        );
+        assert_eq!(
+            store
+                .acquire(&policy, REQUEST_TIME_MS + 300_000)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+/// Maximum number of hits of a rate limit rule within its window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RuleLimit {
+    pub max_hits: u32,
+    pub period_ms: u64,
+}
+
+/// Time to leave between two requests so that they are spread evenly over the window of every
+/// rule, the tightest rule wins. The request being sent and the hit kept in reserve both come
+/// out of the budget, so that a steady pace never runs into the reserve.
+pub fn interval_ms(limits: &[RuleLimit]) -> u64 {
+    limits
+        .iter()
+        .map(|limit| {
+            let hits = u64::from(limit.max_hits.saturating_sub(2).max(1));
+            limit.period_ms.div_ceil(hits)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Default)]
+struct Pace {
+    interval_ms: u64,
+    last_slot_ms: Option<u64>,
+}
+
+/// Hands out evenly spaced send times for the requests of each policy
+#[derive(Debug, Default)]
+pub struct Pacer {
+    policies: HashMap<String, Pace>,
+}
+
+impl Pacer {
+    /// Updates the limits of the policy, as reported by the server
+    pub fn set_limits(&mut self, policy: &str, limits: &[RuleLimit]) {
+        self.policies
+            .entry(policy.to_string())
+            .or_default()
+            .interval_ms = interval_ms(limits);
+    }
+
+    /// Books the next send time of the policy and returns how many milliseconds to wait for it
+    pub fn schedule(&mut self, policy: &str, now_ms: u64) -> u64 {
+        let pace = self.policies.entry(policy.to_string()).or_default();
+        let slot_ms = match pace.last_slot_ms {
+            Some(last_slot_ms) => now_ms.max(last_slot_ms + pace.interval_ms),
+            None => now_ms,
+        };
+        pace.last_slot_ms = Some(slot_ms);
+        slot_ms - now_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poe::rate_limit::{MemoryStore, RateLimitStore, RuleState};
+
+    const POLICY: &str = "stash-request-limit";
+
+    fn limit(max_hits: u32, period_secs: u64) -> RuleLimit {
+        RuleLimit {
+            max_hits,
+            period_ms: period_secs * 1000,
+        }
+    }
+
+    #[test]
+    fn interval_without_limits_is_zero() {
+        assert_eq!(interval_ms(&[]), 0);
+    }
+
+    #[test]
+    fn interval_follows_the_tightest_rule() {
+        assert_eq!(interval_ms(&[limit(12, 10), limit(32, 60)]), 2_000);
+    }
+
+    #[test]
+    fn interval_of_tiny_rules_spans_the_window() {
+        assert_eq!(interval_ms(&[limit(1, 5)]), 5_000);
+    }
+
+    #[test]
+    fn slots_are_booked_one_after_the_other() {
+        let mut pacer = Pacer::default();
+        pacer.set_limits(POLICY, &[limit(7, 10)]);
+
+        assert_eq!(pacer.schedule(POLICY, 0), 0);
+        assert_eq!(pacer.schedule(POLICY, 0), 2_000);
+        assert_eq!(pacer.schedule(POLICY, 500), 3_500);
+        assert_eq!(pacer.schedule(POLICY, 10_000), 0);
+        // New limits apply to the next slot
+        pacer.set_limits(POLICY, &[limit(12, 10)]);
+        assert_eq!(pacer.schedule(POLICY, 10_000), 1_000);
+        // Policies are paced independently
+        assert_eq!(pacer.schedule("other", 500), 0);
+    }
+
+    /// Server side rule with a rolling window, as the API enforces them
+    struct SimulatedRule {
+        limit: RuleLimit,
+        hits_ms: Vec<u64>,
+    }
+
+    impl SimulatedRule {
+        fn new(max_hits: u32, period_secs: u64) -> Self {
+            Self {
+                limit: limit(max_hits, period_secs),
+                hits_ms: Vec::new(),
+            }
+        }
+
+        /// Records a hit and returns the state the middleware derives from the response
+        fn hit(&mut self, now_ms: u64) -> RuleState {
+            self.hits_ms.push(now_ms);
+            let current_hits = self
+                .hits_ms
+                .iter()
+                .filter(|&&hit_ms| hit_ms + self.limit.period_ms > now_ms)
+                .count() as u32;
+            assert!(
+                current_hits < self.limit.max_hits,
+                "{} hits within {}ms at {}ms",
+                current_hits,
+                self.limit.period_ms,
+                now_ms
+            );
+
+            RuleState {
+                remaining_hits: (self.limit.max_hits - current_hits).saturating_sub(1),
+                reset_at_ms: now_ms + self.limit.period_ms,
+            }
+        }
+    }
+
+    /// Sends requests back to back for `duration_ms` of simulated time, the way the middleware
+    /// does, and returns their send times
+    async fn simulate(
+        rules: &mut [SimulatedRule],
+        mut pacer: Option<Pacer>,
+        duration_ms: u64,
+    ) -> Vec<u64> {
+        let store = MemoryStore::default();
+        let mut now_ms = 0;
+        let mut sent_ms = Vec::new();
+
+        while now_ms < duration_ms {
+            let wait_ms = store.acquire(POLICY, now_ms).await.unwrap();
+            if wait_ms > 0 {
+                now_ms += wait_ms;
+                continue;
+            }
+            if let Some(pacer) = &mut pacer {
+                now_ms += pacer.schedule(POLICY, now_ms);
+            }
+
+            let states = rules
+                .iter_mut()
+                .enumerate()
+                .map(|(index, rule)| (index.to_string(), rule.hit(now_ms)))
+                .collect();
+            store.update(POLICY, states).await.unwrap();
+            if let Some(pacer) = &mut pacer {
+                let limits: Vec<_> = rules.iter().map(|rule| rule.limit).collect();
+                pacer.set_limits(POLICY, &limits);
+            }
+            sent_ms.push(now_ms);
+        }
+
+        sent_ms
+    }
+
+    /// Largest number of requests sent within any `window_ms`
+    fn peak(sent_ms: &[u64], window_ms: u64) -> usize {
+        sent_ms
+            .iter()
+            .map(|&start_ms| {
+                sent_ms
+                    .iter()
+                    .filter(|&&ms| ms >= start_ms && ms < start_ms + window_ms)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn burst_sends_the_whole_budget_at_once() {
+        let mut rules = [SimulatedRule::new(10, 10), SimulatedRule::new(30, 60)];
+        let sent_ms = simulate(&mut rules, None, 120_000).await;
+
+        assert!(peak(&sent_ms, 1_000) >= 9, "{sent_ms:?}");
+    }
+
+    #[tokio::test]
+    async fn smooth_pacing_spreads_requests_within_the_limits() {
+        let mut rules = [SimulatedRule::new(10, 10), SimulatedRule::new(30, 60)];
+        let sent_ms = simulate(&mut rules, Some(Pacer::default()), 120_000).await;
+
+        // The longer rule paces requests about two seconds apart
+        assert!(peak(&sent_ms, 1_000) <= 2, "{sent_ms:?}");
+        assert!(peak(&sent_ms, 10_000) <= 6, "{sent_ms:?}");
+        // Without ever stalling until a window resets
+        let longest_gap_ms = sent_ms.windows(2).map(|w| w[1] - w[0]).max().unwrap();
+        assert!(longest_gap_ms <= 2_200, "{sent_ms:?}");
+        assert!(sent_ms.len() >= 55, "{sent_ms:?}");
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Budget left for a rate limit rule until its window resets
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleState {
+    pub remaining_hits: u32,
+    /// Unix timestamp in milliseconds, wall-clock so that it can be shared between processes
+    pub reset_at_ms: u64,
+}
+
+/// Current time as a Unix timestamp in milliseconds
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Storage of the rule states of each policy, shared by every request going through it
+#[async_trait]
+pub trait RateLimitStore: Debug + Send + Sync {
+    /// Reserves a hit on every rule of the policy and returns 0, or returns how many
+    /// milliseconds to wait for an exhausted rule to reset without reserving anything
+    async fn acquire(&self, policy: &str, now_ms: u64) -> Result<u64>;
+
+    /// Replaces the state of the rules of the policy with the one reported by the server
+    async fn update(&self, policy: &str, rules: Vec<(String, RuleState)>) -> Result<()>;
+}
+
+/// Keeps the rule states in process memory
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    policies: Mutex<HashMap<String, HashMap<String, RuleState>>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn acquire(&self, policy: &str, now_ms: u64) -> Result<u64> {
+        let mut policies = self.policies.lock().unwrap();
+        let Some(rules) = policies.get_mut(policy) else {
+            return Ok(0);
+        };
+        rules.retain(|_, state| state.reset_at_ms > now_ms);
+
+        let wait_ms = rules
+            .values()
+            .filter(|state| state.remaining_hits == 0)
+            .map(|state| state.reset_at_ms - now_ms)
+            .max()
+            .unwrap_or(0);
+
+        if wait_ms == 0 {
+            for state in rules.values_mut() {
+                state.remaining_hits -= 1;
+            }
+        }
+
+        Ok(wait_ms)
+    }
+
+    async fn update(&self, policy: &str, updates: Vec<(String, RuleState)>) -> Result<()> {
+        self.policies
+            .lock()
+            .unwrap()
+            .entry(policy.to_string())
+            .or_default()
+            .extend(updates);
+        Ok(())
+    }
+}
+
+/// Same as `MemoryStore::acquire`, as a single atomic step on the server
+const ACQUIRE_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+local wait = 0
+local live = {}
+local fields = redis.call('HGETALL', KEYS[1])
+for i = 1, #fields, 2 do
+    local remaining, reset_at = string.match(fields[i + 1], '(%d+):(%d+)')
+    remaining = tonumber(remaining)
+    reset_at = tonumber(reset_at)
+    if reset_at <= now then
+        redis.call('HDEL', KEYS[1], fields[i])
+    else
+        live[#live + 1] = { fields[i], remaining, reset_at }
+        if remaining == 0 then
+            wait = math.max(wait, reset_at - now)
+        end
+    end
+end
+if wait == 0 then
+    for _, rule in ipairs(live) do
+        redis.call('HSET', KEYS[1], rule[1], (rule[2] - 1) .. ':' .. rule[3])
+    end
+end
+return wait
+";
+
+/// Keeps the rule states in Redis, so that every process crawling with the same OAuth client
+/// works from one budget, which also survives restarts
+#[derive(Clone)]
+pub struct RedisStore {
+    connection: ConnectionManager,
+    key_prefix: String,
+    acquire_script: redis::Script,
+}
+
+impl Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("key_prefix", &self.key_prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStore {
+    pub fn new(connection: ConnectionManager, client_id: &str) -> Self {
+        Self {
+            connection,
+            key_prefix: format!("rate_limit:{client_id}"),
+            acquire_script: redis::Script::new(ACQUIRE_SCRIPT),
+        }
+    }
+
+    fn key(&self, policy: &str) -> String {
+        format!("{}:{policy}", self.key_prefix)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for RedisStore {
+    async fn acquire(&self, policy: &str, now_ms: u64) -> Result<u64> {
+        let mut connection = self.connection.clone();
+        Ok(self
+            .acquire_script
+            .key(self.key(policy))
+            .arg(now_ms)
+            .invoke_async(&mut connection)
+            .await?)
+    }
+
+    async fn update(&self, policy: &str, updates: Vec<(String, RuleState)>) -> Result<()> {
+        let Some(expires_at_ms) = updates.iter().map(|(_, state)| state.reset_at_ms).max() else {
+            return Ok(());
+        };
+
+        let fields = updates
+            .iter()
+            .map(|(rule, state)| {
+                (
+                    rule.as_str(),
+                    format!("{}:{}", state.remaining_hits, state.reset_at_ms),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let key = self.key(policy);
+        let mut connection = self.connection.clone();
+        let _: () = redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .cmd("PEXPIREAT")
+            .arg(&key)
+            .arg(expires_at_ms)
+            .ignore()
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testcontainers_modules::redis::{REDIS_PORT, Redis};
+    use testcontainers_modules::testcontainers::runners::AsyncRunner;
+
+    const POLICY: &str = "stash-request-limit";
+    /// Start of the simulated clock, in the future so that Redis doesn't expire the states
+    const START_MS: u64 = 4_102_444_800_000;
+
+    fn state(remaining_hits: u32, reset_in_ms: u64) -> RuleState {
+        RuleState {
+            remaining_hits,
+            reset_at_ms: START_MS + reset_in_ms,
+        }
+    }
+
+    /// Drives a store through reservations, exhausted and expired rules on a simulated clock,
+    /// returning the wait of every acquisition
+    async fn waits(store: &dyn RateLimitStore) -> Result<Vec<u64>> {
+        let mut waits = vec![store.acquire(POLICY, START_MS).await?];
+
+        store
+            .update(
+                POLICY,
+                vec![
+                    ("Ip:0".to_string(), state(2, 10_000)),
+                    ("Ip:1".to_string(), state(5, 60_000)),
+                ],
+            )
+            .await?;
+        for now_ms in [1_000, 2_000, 3_000, 3_000, 10_000] {
+            waits.push(store.acquire(POLICY, START_MS + now_ms).await?);
+        }
+
+        store
+            .update(POLICY, vec![("Ip:1".to_string(), state(0, 60_000))])
+            .await?;
+        waits.push(store.acquire(POLICY, START_MS + 20_000).await?);
+        waits.push(store.acquire(POLICY, START_MS + 60_000).await?);
+        // Policies don't share their budget
+        waits.push(store.acquire("other", START_MS + 20_000).await?);
+
+        Ok(waits)
+    }
+
+    /// The first two hits come out of the short window, then the crawler waits for it to reset
+    /// without spending the long window's hits, until the long window runs out too
+    const EXPECTED_WAITS: [u64; 9] = [0, 0, 0, 7_000, 7_000, 0, 40_000, 0, 0];
+
+    #[tokio::test]
+    async fn memory_store_waits_for_exhausted_rules() -> Result<()> {
+        assert_eq!(waits(&MemoryStore::default()).await?, EXPECTED_WAITS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redis_store_matches_the_memory_store() -> Result<()> {
+        let container = Redis::default().start().await?;
+        let url = format!(
+            "redis://{}:{}",
+            container.get_host().await?,
+            container.get_host_port_ipv4(REDIS_PORT).await?
+        );
+        let connection = ConnectionManager::new(redis::Client::open(url)?).await?;
+
+        let store = RedisStore::new(connection, "client_id");
+        assert_eq!(waits(&store).await?, EXPECTED_WAITS);
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use strum_macros::{Display, EnumString};
+
+/// Realm of the public stash API, each one has its own change ID stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, clap::ValueEnum)]
+#[strum(serialize_all = "lowercase")]
+pub enum Realm {
+    Pc,
+    Xbox,
+    Sony,
+    Poe2,
+}
+
+impl Realm {
+    /// Parses the realms of the configuration
+    pub fn from_config(realms: &[String]) -> anyhow::Result<Vec<Realm>> {
+        realms
+            .iter()
+            .map(|realm| {
+                realm.parse().map_err(|_| {
+                    anyhow::anyhow!(
+                        "Unknown realm `{realm}` in crawler.realms, expected one of pc, xbox, sony, poe2"
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Public stash endpoint of the realm on the API at `api_url`
+    pub fn public_stash_url(&self, api_url: &str) -> String {
+        match self {
+            Realm::Pc => format!("{api_url}/public-stash-tabs"),
+            realm => format!("{api_url}/public-stash-tabs/{realm}"),
+        }
+    }
+
+    /// poe.ninja endpoint providing a recent change ID for the realm, if there is one
+    pub fn ninja_stats_url(&self) -> Option<&'static str> {
+        match self {
+            Realm::Pc => Some("https://poe.ninja/api/data/getstats"),
+            _ => None,
+        }
+    }
+}
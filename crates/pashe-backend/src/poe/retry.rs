@@ -0,0 +1,102 @@
+use async_trait::async_trait;
+use human_repr::HumanDuration;
+use reqwest::Response;
+use reqwest_middleware::{Middleware, Next};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Delay before the first retry, doubled on every attempt
+const BASE_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay between two attempts
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Whether a response is worth retrying: the server failed, not the request
+pub fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+}
+
+/// Whether an error is worth retrying: the request never got a complete response
+fn is_retryable_error(error: &reqwest_middleware::Error) -> bool {
+    match error {
+        reqwest_middleware::Error::Reqwest(e) => {
+            e.is_connect() || e.is_timeout() || e.is_request() || e.is_body()
+        }
+        reqwest_middleware::Error::Middleware(_) => false,
+    }
+}
+
+/// Randomised delay before the given retry, with full jitter so that concurrent fetches
+/// don't retry in lockstep
+pub fn backoff(retry: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(MAX_DELAY);
+    ceiling.mul_f64(rand::random::<f64>())
+}
+
+/// Retries requests that failed because of the server or the network, with jittered
+/// exponential backoff
+#[derive(Debug)]
+pub struct RetryMiddleware {
+    max_attempts: u32,
+    shutdown_token: CancellationToken,
+}
+
+impl RetryMiddleware {
+    pub fn new(shutdown_token: CancellationToken, max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            shutdown_token,
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RetryMiddleware {
+    #[tracing::instrument(skip_all, level = "trace")]
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let mut attempt = 1;
+
+        loop {
+            let req_clone = req.try_clone().ok_or_else(|| {
+                reqwest_middleware::Error::Middleware(anyhow::anyhow!(
+                    "Request body is not cloneable, cannot retry"
+                ))
+            })?;
+
+            let result = next.clone().run(req_clone, extensions).await;
+            let failure = match &result {
+                Ok(res) if is_retryable_status(res.status()) => format!("HTTP {}", res.status()),
+                Err(e) if is_retryable_error(e) => format!("{e:#}"),
+                _ => return result,
+            };
+
+            if attempt >= self.max_attempts {
+                tracing::warn!("Giving up after {} attempts: {}", attempt, failure);
+                return result;
+            }
+
+            let wait_duration = backoff(attempt - 1);
+            tracing::warn!(
+                "Attempt {}/{} failed: {}, retrying in {}",
+                attempt,
+                self.max_attempts,
+                failure,
+                wait_duration.human_duration()
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(wait_duration) => {},
+                _ = self.shutdown_token.cancelled() => {
+                    tracing::info!("Retry sleep interrupted by shutdown");
+                    return result;
+                }
+            }
+            attempt += 1;
+        }
+    }
+}
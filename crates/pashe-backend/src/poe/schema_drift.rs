@@ -0,0 +1,307 @@
+use crate::poe::types::Stash;
+use crate::telemetry;
+use chrono::{DateTime, Utc};
+use human_repr::HumanCount;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Characters of an offending value kept as an example in the report
+const EXAMPLE_LENGTH: usize = 100;
+
+/// How the payload departs from our types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DriftKind {
+    /// A field our types don't have, ignored when parsing
+    UnknownField,
+    /// A value of a type our types don't accept, failing the whole page
+    UnexpectedType,
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DriftKind::UnknownField => f.write_str("unknown_field"),
+            DriftKind::UnexpectedType => f.write_str("unexpected_type"),
+        }
+    }
+}
+
+/// A field seen in the payload that our types don't cover
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DriftedField {
+    pub kind: DriftKind,
+    /// Path of the field within a stash, with the array indices left out, e.g. `items[].gemTabs`
+    pub path: String,
+    /// JSON type of the value, e.g. `array` or `float`
+    pub value_type: &'static str,
+}
+
+#[derive(Debug)]
+struct Occurrences {
+    count: u64,
+    first_seen: DateTime<Utc>,
+    example: String,
+}
+
+/// Samples the stashes of a realm and records the fields and value types that our types don't
+/// cover, so that new league mechanics are noticed before they break pricing
+#[derive(Debug)]
+pub struct SchemaDrift {
+    realm: String,
+    sample_every: u32,
+    stashes_seen: AtomicU32,
+    fields: Mutex<HashMap<DriftedField, Occurrences>>,
+}
+
+/// Step from a value to one of its children
+enum Step {
+    Index(usize),
+    Key(String),
+}
+
+impl SchemaDrift {
+    pub fn new(realm: String, sample_every: NonZeroU32) -> Self {
+        Self {
+            realm,
+            sample_every: sample_every.get(),
+            stashes_seen: AtomicU32::new(0),
+            fields: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the next stash should be inspected
+    pub fn sample(&self) -> bool {
+        self.stashes_seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_every)
+    }
+
+    /// Parses a sampled stash, recording the fields it ignored and the value it failed at
+    pub fn inspect(&self, value: serde_json::Value) -> Result<Stash, serde_json::Error> {
+        let mut ignored = Vec::new();
+        let mut callback = |path: serde_ignored::Path| ignored.push(ignored_steps(&path));
+
+        let result = serde_path_to_error::deserialize::<_, Stash>(
+            serde_ignored::Deserializer::new(&value, &mut callback),
+        );
+
+        for steps in ignored {
+            self.record(DriftKind::UnknownField, &value, &steps);
+        }
+
+        result.map_err(|e| {
+            let steps = e
+                .path()
+                .iter()
+                .filter_map(|segment| match segment {
+                    serde_path_to_error::Segment::Seq { index } => Some(Step::Index(*index)),
+                    serde_path_to_error::Segment::Map { key } => Some(Step::Key(key.clone())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            self.record(DriftKind::UnexpectedType, &value, &steps);
+
+            e.into_inner()
+        })
+    }
+
+    fn record(&self, kind: DriftKind, stash: &serde_json::Value, steps: &[Step]) {
+        let value = steps.iter().try_fold(stash, |value, step| match step {
+            Step::Index(index) => value.get(index),
+            Step::Key(key) => value.get(key),
+        });
+        let field = DriftedField {
+            kind,
+            path: drift_path(steps),
+            value_type: value.map_or("missing", value_type),
+        };
+
+        metrics::counter!(
+            telemetry::SCHEMA_DRIFT,
+            "realm" => self.realm.clone(),
+            "kind" => kind.to_string(),
+            "field" => field.path.clone(),
+        )
+        .increment(1);
+
+        self.fields
+            .lock()
+            .unwrap()
+            .entry(field)
+            .or_insert_with(|| Occurrences {
+                count: 0,
+                first_seen: Utc::now(),
+                example: value
+                    .map(|value| value.to_string().chars().take(EXAMPLE_LENGTH).collect())
+                    .unwrap_or_default(),
+            })
+            .count += 1;
+    }
+
+    /// Fields recorded so far, with the number of times they were seen
+    pub fn fields(&self) -> Vec<(DriftedField, u64)> {
+        sorted(&self.fields.lock().unwrap())
+            .into_iter()
+            .map(|(field, occurrences)| (field.clone(), occurrences.count))
+            .collect()
+    }
+
+    /// Logs the fields recorded so far
+    pub fn report(&self) {
+        let fields = self.fields.lock().unwrap();
+        let stashes_sampled = self
+            .stashes_seen
+            .load(Ordering::Relaxed)
+            .div_ceil(self.sample_every);
+        if fields.is_empty() {
+            info!(
+                "No schema drift on {} in {} sampled stashes",
+                self.realm,
+                stashes_sampled.human_count_bare()
+            );
+            return;
+        }
+
+        let fields = sorted(&fields);
+        warn!(
+            "Schema drift on {}: {} fields not covered by our types in {} sampled stashes",
+            self.realm,
+            fields.len(),
+            stashes_sampled.human_count_bare()
+        );
+        for (field, occurrences) in fields {
+            warn!(
+                "  {} `{}` ({}): seen {} times since {}, e.g. {}",
+                field.kind,
+                field.path,
+                field.value_type,
+                occurrences.count.human_count_bare(),
+                occurrences.first_seen.format("%Y-%m-%d %H:%M"),
+                occurrences.example
+            );
+        }
+    }
+
+    /// Reports the recorded fields at every interval until shutdown
+    pub async fn report_periodically(&self, interval: Duration, shutdown_token: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, before anything was sampled
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.report(),
+                _ = shutdown_token.cancelled() => return,
+            }
+        }
+    }
+}
+
+/// Orders the fields by path, then value type
+fn sorted(fields: &HashMap<DriftedField, Occurrences>) -> Vec<(&DriftedField, &Occurrences)> {
+    let mut fields = fields.iter().collect::<Vec<_>>();
+    fields.sort_by(|(a, _), (b, _)| (&a.path, a.value_type).cmp(&(&b.path, b.value_type)));
+    fields
+}
+
+fn ignored_steps(path: &serde_ignored::Path) -> Vec<Step> {
+    let mut steps = match path {
+        serde_ignored::Path::Root => return Vec::new(),
+        serde_ignored::Path::Seq { parent, .. }
+        | serde_ignored::Path::Map { parent, .. }
+        | serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_steps(parent),
+    };
+    match path {
+        serde_ignored::Path::Seq { index, .. } => steps.push(Step::Index(*index)),
+        serde_ignored::Path::Map { key, .. } => steps.push(Step::Key(key.clone())),
+        _ => {}
+    }
+    steps
+}
+
+/// Renders a path without its array indices, so that every occurrence of a field shares it
+fn drift_path(steps: &[Step]) -> String {
+    let mut path = String::new();
+    for step in steps {
+        match step {
+            Step::Index(_) => path.push_str("[]"),
+            Step::Key(key) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+        }
+    }
+    path
+}
+
+fn value_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "bool",
+        serde_json::Value::Number(number) if number.is_f64() => "float",
+        serde_json::Value::Number(_) => "integer",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poe::types::fixtures::item;
+    use serde_json::json;
+
+    fn stash(items: serde_json::Value) -> serde_json::Value {
+        json!({
+            "id": "stash",
+            "public": true,
+            "stashType": "PremiumStash",
+            "items": items,
+        })
+    }
+
+    #[test]
+    fn records_unknown_fields_once_per_path_and_type() {
+        let drift = SchemaDrift::new("pc".to_string(), NonZeroU32::MIN);
+
+        drift
+            .inspect(stash(json!([
+                item(json!({"gemTabs": []})),
+                item(json!({"gemTabs": [], "forumNote": "~b/o 1 chaos"})),
+            ])))
+            .unwrap();
+
+        let fields = drift.fields();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].0.path, "items[].forumNote");
+        assert_eq!(fields[0].0.value_type, "string");
+        assert_eq!(fields[1].0.path, "items[].gemTabs");
+        assert_eq!(fields[1].0.value_type, "array");
+        assert_eq!(fields[1].1, 2);
+    }
+
+    #[test]
+    fn records_unexpected_value_types() {
+        let drift = SchemaDrift::new("pc".to_string(), NonZeroU32::MIN);
+
+        let result = drift.inspect(stash(json!([item(json!({"ilvl": 1.5}))])));
+
+        assert!(result.is_err());
+        let fields = drift.fields();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].0.kind, DriftKind::UnexpectedType);
+        assert_eq!(fields[0].0.path, "items[].ilvl");
+        assert_eq!(fields[0].0.value_type, "float");
+    }
+}
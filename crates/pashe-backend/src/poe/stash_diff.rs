@@ -0,0 +1,419 @@
+use crate::db::{StashEventKind, StashListing};
+use crate::poe::currency::ListingCurrency;
+use crate::poe::price::ListingPrice;
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+
+/// Contents of a stash in an update, as item IDs and their listing price
+#[derive(Debug)]
+pub struct StashContents {
+    pub stash_id: String,
+    pub league: Option<String>,
+    pub items: HashMap<String, Option<ListingPrice>>,
+}
+
+/// A listing that changed between two updates of a stash
+#[derive(Debug)]
+pub struct ItemChange {
+    pub league: String,
+    pub stash_id: String,
+    pub item_id: String,
+    pub kind: StashEventKind,
+    pub price: Option<ListingPrice>,
+    pub previous_price: Option<ListingPrice>,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    league: String,
+    items: HashMap<String, Option<ListingPrice>>,
+    /// Position in the eviction order
+    touched: u64,
+}
+
+/// Last known contents of the most recently updated stashes, used to diff the full stashes resent
+/// by the API.
+///
+/// Past the capacity, the least recently updated stash is evicted. Stashes missing from memory,
+/// after an eviction or a restart, are restored from `current_listings` before being diffed.
+#[derive(Debug)]
+pub struct StashSnapshots {
+    stashes: HashMap<String, Snapshot>,
+    /// Stash IDs by the update that last touched them, oldest first
+    order: BTreeMap<u64, String>,
+    capacity: NonZeroUsize,
+    updates: u64,
+}
+
+impl Default for StashSnapshots {
+    fn default() -> Self {
+        Self::with_capacity(NonZeroUsize::new(200_000).unwrap())
+    }
+}
+
+impl StashSnapshots {
+    pub fn with_capacity(capacity: NonZeroUsize) -> Self {
+        Self {
+            stashes: HashMap::new(),
+            order: BTreeMap::new(),
+            capacity,
+            updates: 0,
+        }
+    }
+
+    /// Number of stashes held in memory
+    pub fn len(&self) -> usize {
+        self.stashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stashes.is_empty()
+    }
+
+    /// Returns the IDs of the stashes without a snapshot in memory
+    pub fn missing<'a>(&self, contents: &'a [StashContents]) -> Vec<&'a str> {
+        contents
+            .iter()
+            .map(|contents| contents.stash_id.as_str())
+            .filter(|stash_id| !self.stashes.contains_key(*stash_id))
+            .collect()
+    }
+
+    /// Restores the snapshots of stashes from their stored listings.
+    ///
+    /// Only priced items are stored, the unpriced items of a restored stash are reported as added
+    /// on its next update.
+    pub fn restore(&mut self, listings: Vec<StashListing>) {
+        for listing in listings {
+            let currency = if listing.price_currency_known {
+                ListingCurrency::Known(listing.price_currency)
+            } else {
+                ListingCurrency::Unknown(listing.price_currency)
+            };
+            let price = ListingPrice {
+                listing_type: listing.listing_type,
+                quantity: listing.price_quantity,
+                currency,
+            };
+
+            if !self.stashes.contains_key(&listing.stash_id) {
+                let touched = self.touch(&listing.stash_id);
+                self.stashes.insert(
+                    listing.stash_id.clone(),
+                    Snapshot {
+                        league: listing.league,
+                        items: HashMap::new(),
+                        touched,
+                    },
+                );
+            }
+            if let Some(snapshot) = self.stashes.get_mut(&listing.stash_id) {
+                snapshot.items.insert(listing.item_id, Some(price));
+            }
+        }
+        self.evict();
+    }
+
+    /// Returns what changed in a stash since its snapshot, leaving the snapshot as it is until the
+    /// changes are committed
+    pub fn diff(&self, contents: &StashContents) -> Vec<ItemChange> {
+        let previous = self.stashes.get(&contents.stash_id);
+        let empty = HashMap::new();
+        let previous_items = previous.map_or(&empty, |snapshot| &snapshot.items);
+        let previous_league = previous.map(|snapshot| snapshot.league.as_str());
+        // Emptied or private stashes no longer carry their league
+        let league = contents
+            .league
+            .as_deref()
+            .or(previous_league)
+            .unwrap_or_default();
+        // Items of a stash that moved league left the previous one and are new to the next
+        let moved_league = previous_league.is_some_and(|previous_league| previous_league != league);
+
+        let change = |league: &str, item_id: &str, kind, price, previous_price| ItemChange {
+            league: league.to_string(),
+            stash_id: contents.stash_id.clone(),
+            item_id: item_id.to_string(),
+            kind,
+            price,
+            previous_price,
+        };
+
+        let mut changes = Vec::new();
+        for (item_id, price) in &contents.items {
+            match previous_items.get(item_id) {
+                Some(previous_price) if !moved_league && previous_price != price => {
+                    changes.push(change(
+                        league,
+                        item_id,
+                        StashEventKind::Repriced,
+                        price.clone(),
+                        previous_price.clone(),
+                    ))
+                }
+                Some(_) if !moved_league => {}
+                _ => changes.push(change(
+                    league,
+                    item_id,
+                    StashEventKind::Added,
+                    price.clone(),
+                    None,
+                )),
+            }
+        }
+
+        for (item_id, previous_price) in previous_items {
+            if moved_league || !contents.items.contains_key(item_id) {
+                changes.push(change(
+                    previous_league.unwrap_or(league),
+                    item_id,
+                    StashEventKind::Removed,
+                    None,
+                    previous_price.clone(),
+                ));
+            }
+        }
+
+        changes
+    }
+
+    /// Replaces the snapshot of a stash with its new contents
+    pub fn commit(&mut self, contents: StashContents) {
+        let previous = self.stashes.remove(&contents.stash_id);
+        if let Some(previous) = &previous {
+            self.order.remove(&previous.touched);
+        }
+        if contents.items.is_empty() {
+            return;
+        }
+
+        let league = contents
+            .league
+            .or_else(|| previous.map(|snapshot| snapshot.league))
+            .unwrap_or_default();
+        let touched = self.touch(&contents.stash_id);
+        self.stashes.insert(
+            contents.stash_id,
+            Snapshot {
+                league,
+                items: contents.items,
+                touched,
+            },
+        );
+        self.evict();
+    }
+
+    fn touch(&mut self, stash_id: &str) -> u64 {
+        self.updates += 1;
+        self.order.insert(self.updates, stash_id.to_string());
+        self.updates
+    }
+
+    /// Drops the least recently updated stashes past the capacity
+    fn evict(&mut self) {
+        while self.stashes.len() > self.capacity.get() {
+            let Some((_, stash_id)) = self.order.pop_first() else {
+                break;
+            };
+            self.stashes.remove(&stash_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ListingType;
+
+    fn chaos(quantity: f32) -> Option<ListingPrice> {
+        Some(ListingPrice {
+            listing_type: ListingType::Buyout,
+            quantity,
+            currency: ListingCurrency::Known("chaos".to_string()),
+        })
+    }
+
+    fn contents(league: Option<&str>, items: &[(&str, Option<ListingPrice>)]) -> StashContents {
+        StashContents {
+            stash_id: "stash".to_string(),
+            league: league.map(str::to_string),
+            items: items
+                .iter()
+                .map(|(item_id, price)| (item_id.to_string(), price.clone()))
+                .collect(),
+        }
+    }
+
+    /// Diffs then commits the contents, returning the changes ordered by item ID and league
+    fn update(
+        snapshots: &mut StashSnapshots,
+        contents: StashContents,
+    ) -> Vec<(String, String, StashEventKind)> {
+        let mut changes = snapshots
+            .diff(&contents)
+            .into_iter()
+            .map(|change| (change.item_id, change.league, change.kind))
+            .collect::<Vec<_>>();
+        changes.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        snapshots.commit(contents);
+        changes
+    }
+
+    fn change(
+        item_id: &str,
+        league: &str,
+        kind: StashEventKind,
+    ) -> (String, String, StashEventKind) {
+        (item_id.to_string(), league.to_string(), kind)
+    }
+
+    #[test]
+    fn reports_added_removed_and_repriced_items() {
+        let mut snapshots = StashSnapshots::default();
+
+        let changes = update(
+            &mut snapshots,
+            contents(Some("Standard"), &[("a", chaos(1.0)), ("b", chaos(2.0))]),
+        );
+        assert_eq!(
+            changes,
+            [
+                change("a", "Standard", StashEventKind::Added),
+                change("b", "Standard", StashEventKind::Added),
+            ]
+        );
+
+        let changes = update(
+            &mut snapshots,
+            contents(Some("Standard"), &[("a", chaos(1.0)), ("c", chaos(3.0))]),
+        );
+        assert_eq!(
+            changes,
+            [
+                change("b", "Standard", StashEventKind::Removed),
+                change("c", "Standard", StashEventKind::Added),
+            ]
+        );
+
+        let changes = update(
+            &mut snapshots,
+            contents(Some("Standard"), &[("a", chaos(5.0)), ("c", chaos(3.0))]),
+        );
+        assert_eq!(changes, [change("a", "Standard", StashEventKind::Repriced)]);
+    }
+
+    #[test]
+    fn keeps_the_league_of_emptied_stashes() {
+        let mut snapshots = StashSnapshots::default();
+        update(
+            &mut snapshots,
+            contents(Some("Standard"), &[("a", chaos(1.0))]),
+        );
+
+        let changes = update(&mut snapshots, contents(None, &[]));
+
+        assert_eq!(changes, [change("a", "Standard", StashEventKind::Removed)]);
+    }
+
+    #[test]
+    fn moves_items_to_the_new_league_of_a_stash() {
+        let mut snapshots = StashSnapshots::default();
+        update(
+            &mut snapshots,
+            contents(Some("Mercenaries"), &[("a", chaos(1.0))]),
+        );
+
+        let changes = update(
+            &mut snapshots,
+            contents(Some("Standard"), &[("a", chaos(1.0))]),
+        );
+
+        assert_eq!(
+            changes,
+            [
+                change("a", "Mercenaries", StashEventKind::Removed),
+                change("a", "Standard", StashEventKind::Added),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_the_snapshot_alone_until_committed() {
+        let mut snapshots = StashSnapshots::default();
+        update(
+            &mut snapshots,
+            contents(Some("Standard"), &[("a", chaos(1.0))]),
+        );
+
+        let repriced = contents(Some("Standard"), &[("a", chaos(2.0))]);
+        assert_eq!(snapshots.diff(&repriced).len(), 1);
+        // The changes weren't stored, so the next attempt reports them again
+        assert_eq!(snapshots.diff(&repriced).len(), 1);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_updated_stash() {
+        let mut snapshots = StashSnapshots::with_capacity(NonZeroUsize::new(2).unwrap());
+        for stash_id in ["a", "b"] {
+            snapshots.commit(StashContents {
+                stash_id: stash_id.to_string(),
+                ..contents(Some("Standard"), &[(stash_id, chaos(1.0))])
+            });
+        }
+        // Updating the first stash makes the second one the oldest
+        snapshots.commit(StashContents {
+            stash_id: "a".to_string(),
+            ..contents(Some("Standard"), &[("a", chaos(2.0))])
+        });
+        snapshots.commit(StashContents {
+            stash_id: "c".to_string(),
+            ..contents(Some("Standard"), &[("c", chaos(1.0))])
+        });
+
+        assert_eq!(snapshots.len(), 2);
+        let updates = ["a", "b", "c"].map(|stash_id| StashContents {
+            stash_id: stash_id.to_string(),
+            ..contents(Some("Standard"), &[])
+        });
+        assert_eq!(snapshots.missing(&updates), ["b"]);
+    }
+
+    #[test]
+    fn diffs_restored_stashes_against_their_stored_listings() {
+        let mut snapshots = StashSnapshots::default();
+        let listing = |item_id: &str, price_currency: &str, price_currency_known| StashListing {
+            stash_id: "stash".to_string(),
+            league: "Standard".to_string(),
+            item_id: item_id.to_string(),
+            listing_type: ListingType::Buyout,
+            price_quantity: 1.0,
+            price_currency: price_currency.to_string(),
+            price_currency_known,
+        };
+        snapshots.restore(vec![
+            listing("a", "chaos", true),
+            listing("b", "shiny", false),
+        ]);
+
+        let changes = update(
+            &mut snapshots,
+            contents(
+                Some("Standard"),
+                &[
+                    ("a", chaos(1.0)),
+                    (
+                        "b",
+                        Some(ListingPrice {
+                            listing_type: ListingType::Buyout,
+                            quantity: 1.0,
+                            currency: ListingCurrency::Unknown("shiny".to_string()),
+                        }),
+                    ),
+                    ("c", chaos(3.0)),
+                ],
+            ),
+        );
+
+        assert_eq!(changes, [change("c", "Standard", StashEventKind::Added)]);
+    }
+}
@@ -0,0 +1,304 @@
+//! Matching of mod texts to the stat templates of the trade site.
+//!
+//! The templates are read from a file in the format of the trade site's `/api/trade/data/stats`
+//! response, where each stat is a text with `#` standing for its values (`+# to maximum Life`) and
+//! an ID prefixed by the kind of mod it applies to (`explicit.stat_3299347043`).
+//!
+//! The bundled `data/stats.json` is a starter subset of common explicit, implicit, crafted,
+//! fractured and enchant mods, so most mods are still counted as unmatched. For full coverage,
+//! replace it with the complete response:
+//!
+//! ```text
+//! curl -A "pashe" https://www.pathofexile.com/api/trade/data/stats -o data/stats.json
+//! ```
+
+use crate::poe::types::Item;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Kind of mod, the trade site keeps separate stat IDs for each of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModKind {
+    Enchant,
+    Implicit,
+    Explicit,
+    Crafted,
+    Fractured,
+    Scourge,
+    Crucible,
+    Utility,
+    Rune,
+}
+
+impl ModKind {
+    /// Stat group of the trade site listing the templates of this kind of mod
+    fn group(self) -> &'static str {
+        match self {
+            ModKind::Enchant => "enchant",
+            ModKind::Implicit => "implicit",
+            ModKind::Explicit | ModKind::Utility => "explicit",
+            ModKind::Crafted => "crafted",
+            ModKind::Fractured => "fractured",
+            ModKind::Scourge => "scourge",
+            ModKind::Crucible => "crucible",
+            ModKind::Rune => "rune",
+        }
+    }
+}
+
+/// A mod matched to its stat template
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedMod<'a> {
+    pub stat_id: &'a str,
+    /// Numbers of the mod text, in the order of the template's `#`
+    pub values: Vec<f32>,
+}
+
+/// Stats of an item, in the order of its mods
+#[derive(Debug, Default)]
+pub struct ItemStats {
+    pub stat_ids: Vec<String>,
+    pub stat_values: Vec<Vec<f32>>,
+    /// Mods that matched no template
+    pub unmatched: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsFile {
+    result: Vec<StatGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatGroup {
+    id: String,
+    entries: Vec<StatEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatEntry {
+    id: String,
+    text: String,
+}
+
+/// Stat templates by group, then by template text
+#[derive(Debug, Default)]
+pub struct StatTemplates {
+    groups: HashMap<String, HashMap<String, String>>,
+}
+
+impl StatTemplates {
+    /// Loads the stat templates from a copy of the trade site's stats
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read stat templates {}", path.display()))?;
+        Self::from_json(&contents)
+            .with_context(|| format!("Failed to parse stat templates {}", path.display()))
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self> {
+        let stats_file: StatsFile = serde_json::from_str(contents)?;
+
+        let groups = stats_file
+            .result
+            .into_iter()
+            .map(|group| {
+                let mut templates = HashMap::with_capacity(group.entries.len());
+                for entry in group.entries {
+                    // Keep the first of the stats sharing a text, as the trade site lists them
+                    templates.entry(entry.text).or_insert(entry.id);
+                }
+                (group.id, templates)
+            })
+            .collect();
+
+        Ok(Self { groups })
+    }
+
+    /// Number of templates across all groups
+    pub fn len(&self) -> usize {
+        self.groups.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Matches a mod text to its stat template, falling back to the explicit stats for the kinds
+    /// of mod the templates don't cover
+    pub fn parse_mod(&self, kind: ModKind, text: &str) -> Option<ParsedMod<'_>> {
+        let (template, values) = split_values(text);
+        // Negative values of a `+#` stat are shown with a minus sign instead of the plus
+        let positive_template = template.replace("-#", "+#");
+
+        let stat_id = [kind.group(), ModKind::Explicit.group()]
+            .into_iter()
+            .filter_map(|group| self.groups.get(group))
+            .find_map(|templates| {
+                templates
+                    .get(&template)
+                    .or_else(|| templates.get(&positive_template))
+            })?;
+
+        Some(ParsedMod { stat_id, values })
+    }
+
+    /// Matches every mod of an item to its stat template, veiled mods are left out as they only
+    /// name the mod they will be unveiled into
+    pub fn parse_item(&self, item: &Item) -> ItemStats {
+        let mod_lists = [
+            (ModKind::Enchant, &item.enchant_mods),
+            (ModKind::Implicit, &item.implicit_mods),
+            (ModKind::Explicit, &item.explicit_mods),
+            (ModKind::Crafted, &item.crafted_mods),
+            (ModKind::Fractured, &item.fractured_mods),
+            (ModKind::Scourge, &item.scourge_mods),
+            (ModKind::Crucible, &item.crucible_mods),
+            (ModKind::Utility, &item.utility_mods),
+            (ModKind::Rune, &item.rune_mods),
+        ];
+
+        let mut stats = ItemStats::default();
+        for (kind, mods) in mod_lists {
+            for text in mods.iter().flatten() {
+                match self.parse_mod(kind, text) {
+                    Some(parsed) => {
+                        stats.stat_ids.push(parsed.stat_id.to_string());
+                        stats.stat_values.push(parsed.values);
+                    }
+                    None => stats.unmatched += 1,
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+/// Replaces the numbers of a mod text by `#`, returning the template and the numbers, negative
+/// when preceded by a minus sign
+pub fn split_values(text: &str) -> (String, Vec<f32>) {
+    let mut template = String::with_capacity(text.len());
+    let mut values = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if !c.is_ascii_digit() {
+            template.push(c);
+            continue;
+        }
+
+        let mut end = start + 1;
+        while let Some(&(index, c)) = chars.peek() {
+            let is_decimal_point = c == '.'
+                && text[index + 1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_digit());
+            if !c.is_ascii_digit() && !is_decimal_point {
+                break;
+            }
+            chars.next();
+            end = index + 1;
+        }
+
+        let value = text[start..end].parse::<f32>().unwrap_or_default();
+        values.push(if template.ends_with('-') {
+            -value
+        } else {
+            value
+        });
+        template.push('#');
+    }
+
+    (template, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: &str = r#"{
+        "result": [
+            {
+                "id": "explicit",
+                "label": "Explicit",
+                "entries": [
+                    {"id": "explicit.stat_3299347043", "text": "+# to maximum Life", "type": "explicit"},
+                    {"id": "explicit.stat_3372524247", "text": "+#% to Fire Resistance", "type": "explicit"},
+                    {"id": "explicit.stat_1940865751", "text": "Adds # to # Physical Damage", "type": "explicit"}
+                ]
+            },
+            {
+                "id": "implicit",
+                "label": "Implicit",
+                "entries": [
+                    {"id": "implicit.stat_3299347043", "text": "+# to maximum Life", "type": "implicit"}
+                ]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn splits_values_out_of_mod_texts() {
+        assert_eq!(
+            split_values("+45 to maximum Life"),
+            ("+# to maximum Life".to_string(), vec![45.0])
+        );
+        assert_eq!(
+            split_values("Adds 10 to 20.5 Physical Damage"),
+            ("Adds # to # Physical Damage".to_string(), vec![10.0, 20.5])
+        );
+        assert_eq!(
+            split_values("-12% to Fire Resistance"),
+            ("-#% to Fire Resistance".to_string(), vec![-12.0])
+        );
+        assert_eq!(
+            split_values("Cannot be Frozen."),
+            ("Cannot be Frozen.".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn matches_mods_to_the_stats_of_their_kind() {
+        let templates = StatTemplates::from_json(STATS).unwrap();
+
+        let parsed = templates
+            .parse_mod(ModKind::Implicit, "+45 to maximum Life")
+            .unwrap();
+        assert_eq!(parsed.stat_id, "implicit.stat_3299347043");
+        assert_eq!(parsed.values, [45.0]);
+
+        // No crafted stats, the explicit ones share their texts
+        let parsed = templates
+            .parse_mod(ModKind::Crafted, "Adds 3 to 7 Physical Damage")
+            .unwrap();
+        assert_eq!(parsed.stat_id, "explicit.stat_1940865751");
+        assert_eq!(parsed.values, [3.0, 7.0]);
+
+        let parsed = templates
+            .parse_mod(ModKind::Explicit, "-12% to Fire Resistance")
+            .unwrap();
+        assert_eq!(parsed.stat_id, "explicit.stat_3372524247");
+        assert_eq!(parsed.values, [-12.0]);
+
+        assert_eq!(
+            templates.parse_mod(ModKind::Explicit, "Cannot be Frozen"),
+            None
+        );
+    }
+
+    #[test]
+    fn loads_the_bundled_stats() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../data/stats.json");
+        let templates = StatTemplates::load(&path).unwrap();
+
+        assert!(!templates.is_empty());
+        assert!(
+            templates
+                .parse_mod(ModKind::Explicit, "+92 to maximum Energy Shield")
+                .is_some()
+        );
+    }
+}
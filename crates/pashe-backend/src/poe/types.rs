@@ -1,12 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PublicStashTabs {
-    pub next_change_id: String,
-    pub stashes: Vec<Stash>,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Stash {
@@ -296,3 +290,31 @@ pub struct UltimatumMod {
     pub ultimatum_mod_type: String, // LowCardinality
     pub tier: i64,
 }
+
+/// Shared by the tests that build items from JSON
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use serde_json::json;
+
+    /// JSON of a minimal item, with the given fields added or replaced
+    pub(crate) fn item(fields: serde_json::Value) -> serde_json::Value {
+        let mut item = json!({
+            "verified": false,
+            "w": 1,
+            "h": 1,
+            "icon": "icon",
+            "league": "Standard",
+            "id": "item",
+            "name": "",
+            "typeLine": "Chaos Orb",
+            "baseType": "Chaos Orb",
+            "identified": true,
+            "ilvl": 0,
+            "frameType": 5,
+        });
+        item.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        item
+    }
+}
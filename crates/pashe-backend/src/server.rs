@@ -0,0 +1,146 @@
+use crate::{db, health::Health, health::IngestionReport};
+use anyhow::{Context, Result};
+use axum::{Json, Router, extract::State, http::StatusCode, routing::get};
+use metrics_exporter_prometheus::PrometheusHandle;
+use redis::aio::ConnectionManager;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Maximum time a dependency check can take before it is reported unreachable
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Everything the operational endpoints report on
+#[derive(Clone)]
+pub struct ServerState {
+    pub metrics: PrometheusHandle,
+    pub health: Arc<Health>,
+    pub db: db::Client,
+    /// Shared Redis connection, only opened when something is stored in Redis
+    pub redis: Option<ConnectionManager>,
+    pub realms: Vec<String>,
+    pub stall_timeout: Duration,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthReport {
+    healthy: bool,
+    ingestion: IngestionReport,
+}
+
+#[derive(Debug, Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    token_valid: bool,
+    clickhouse_reachable: bool,
+    /// Redis is an optional dependency, left out when nothing is stored in it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redis_reachable: Option<bool>,
+    ingestion: IngestionReport,
+}
+
+/// Serves the operational endpoints until shutdown
+pub async fn serve(
+    address: SocketAddr,
+    state: ServerState,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(render_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind the metrics server to {address}"))?;
+    info!("Serving metrics and health endpoints on http://{}", address);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_token.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+async fn render_metrics(State(state): State<ServerState>) -> String {
+    state.metrics.render()
+}
+
+/// Liveness: fails once ingestion stalls, so that the container gets flagged
+async fn healthz(State(state): State<ServerState>) -> (StatusCode, Json<HealthReport>) {
+    let ingestion = state.health.ingestion(&state.realms, state.stall_timeout);
+    let healthy = !ingestion.stalled;
+
+    (
+        status_code(healthy),
+        Json(HealthReport { healthy, ingestion }),
+    )
+}
+
+/// Readiness: fails while the token is invalid or a dependency in use can't be reached
+async fn readyz(State(state): State<ServerState>) -> (StatusCode, Json<ReadinessReport>) {
+    let redis_ping = async {
+        let mut redis = state.redis.clone()?;
+        let ping = redis::cmd("PING");
+        let ping = ping.query_async::<()>(&mut redis);
+        Some(matches!(
+            tokio::time::timeout(CHECK_TIMEOUT, ping).await,
+            Ok(Ok(()))
+        ))
+    };
+    let (clickhouse, redis) = tokio::join!(
+        tokio::time::timeout(CHECK_TIMEOUT, state.db.ping()),
+        redis_ping,
+    );
+    let clickhouse_reachable = matches!(clickhouse, Ok(Ok(())));
+    let redis_reachable = redis;
+    let token_valid = state.health.token_valid();
+    let ready = token_valid && clickhouse_reachable && redis_reachable.unwrap_or(true);
+
+    (
+        status_code(ready),
+        Json(ReadinessReport {
+            ready,
+            token_valid,
+            clickhouse_reachable,
+            redis_reachable,
+            ingestion: state.health.ingestion(&state.realms, state.stall_timeout),
+        }),
+    )
+}
+
+fn status_code(ok: bool) -> StatusCode {
+    if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// Queries the liveness endpoint of a running backend, for container health checks
+pub async fn check_health(address: SocketAddr) -> Result<()> {
+    // The server usually listens on all interfaces, which isn't a valid destination
+    let address = if address.ip().is_unspecified() {
+        SocketAddr::from(([127, 0, 0, 1], address.port()))
+    } else {
+        address
+    };
+
+    let response = reqwest::Client::new()
+        .get(format!("http://{address}/healthz"))
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach the backend at {address}"))?;
+
+    let status = response.status();
+    let report = response.text().await?;
+    println!("{report}");
+
+    anyhow::ensure!(status.is_success(), "Backend is unhealthy: HTTP {status}");
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use metrics::{Unit, describe_counter, describe_gauge, describe_histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+pub const PAGES_FETCHED: &str = "pashe_pages_fetched_total";
+pub const PARSE_FAILURES: &str = "pashe_parse_failures_total";
+pub const COMPRESSED_BYTES: &str = "pashe_compressed_bytes_total";
+pub const DECOMPRESSED_BYTES: &str = "pashe_decompressed_bytes_total";
+pub const ITEMS_PARSED: &str = "pashe_items_parsed_total";
+pub const ITEMS_PRICED: &str = "pashe_items_priced_total";
+pub const MODS_UNMATCHED: &str = "pashe_mods_unmatched_total";
+pub const UNKNOWN_CURRENCIES: &str = "pashe_unknown_currencies_total";
+pub const INSERT_DURATION: &str = "pashe_insert_duration_seconds";
+pub const PAGES_IN_FLIGHT: &str = "pashe_pages_in_flight";
+pub const PROCESSING_QUEUE_DEPTH: &str = "pashe_processing_queue_depth";
+pub const RATE_LIMIT_REMAINING_HITS: &str = "pashe_rate_limit_remaining_hits";
+pub const SCHEMA_DRIFT: &str = "pashe_schema_drift_total";
+pub const STASH_SNAPSHOTS: &str = "pashe_stash_snapshots";
+pub const STASH_SNAPSHOTS_RESTORED: &str = "pashe_stash_snapshots_restored_total";
+
+/// Installs the global Prometheus recorder, the returned handle renders the current metrics
+pub fn install_recorder() -> Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(INSERT_DURATION.to_string()),
+            &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0],
+        )?
+        .install_recorder()?;
+
+    describe_counter!(
+        PAGES_FETCHED,
+        "Public stash pages fetched successfully, per realm"
+    );
+    describe_counter!(
+        PARSE_FAILURES,
+        "Public stash pages that could not be decoded, per realm"
+    );
+    describe_counter!(
+        COMPRESSED_BYTES,
+        Unit::Bytes,
+        "Compressed bytes of the decoded pages, per realm"
+    );
+    describe_counter!(
+        DECOMPRESSED_BYTES,
+        Unit::Bytes,
+        "Decompressed bytes of the decoded pages, per realm"
+    );
+    describe_counter!(ITEMS_PARSED, "Items parsed from stash pages, per realm");
+    describe_counter!(ITEMS_PRICED, "Parsed items with a listing price, per realm");
+    describe_counter!(
+        MODS_UNMATCHED,
+        "Mods of priced items matching no stat template, per realm"
+    );
+    describe_counter!(
+        UNKNOWN_CURRENCIES,
+        "Priced items whose currency is missing from the catalogue, per realm"
+    );
+    describe_histogram!(
+        INSERT_DURATION,
+        Unit::Seconds,
+        "Latency of ClickHouse batch inserts, per table"
+    );
+    describe_gauge!(
+        PAGES_IN_FLIGHT,
+        "Pages being fetched concurrently, per realm"
+    );
+    describe_gauge!(
+        PROCESSING_QUEUE_DEPTH,
+        "Decoded pages waiting for the processor, per realm"
+    );
+    describe_gauge!(
+        RATE_LIMIT_REMAINING_HITS,
+        "Hits left before the rate limit rule is exhausted, per rule"
+    );
+    describe_gauge!(
+        STASH_SNAPSHOTS,
+        "Stashes whose last contents are kept in memory, per realm"
+    );
+    describe_counter!(
+        STASH_SNAPSHOTS_RESTORED,
+        "Stashes missing from memory looked up in the database, per realm"
+    );
+    describe_counter!(
+        SCHEMA_DRIFT,
+        "Sampled fields not covered by the stash types, per realm, kind and field"
+    );
+
+    Ok(handle)
+}
@@ -0,0 +1,257 @@
+use anyhow::Result;
+use chrono::Utc;
+use pashe_backend::health::Health;
+use pashe_backend::poe::realm::Realm;
+use pashe_backend::{crawler, db};
+use pashe_config::Config;
+use poe_mock::{Fault, PoeMock};
+use serial_test::serial;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use testcontainers_modules::testcontainers::{ContainerAsync, ImageExt, runners::AsyncRunner};
+use tokio_util::sync::CancellationToken;
+
+const MIGRATIONS_DIRECTORY: &str = "../../migrations";
+
+const USER: &str = "pashe";
+const PASSWORD: &str = "pashe";
+const DATABASE: &str = "pashe";
+
+/// A migrated ClickHouse, the container is stopped when dropped
+struct ClickHouse {
+    _container: ContainerAsync<testcontainers_modules::clickhouse::ClickHouse>,
+    url: String,
+    client: clickhouse::Client,
+}
+
+async fn clickhouse() -> Result<ClickHouse> {
+    if let Err(e) = tracing_subscriber::fmt::try_init() {
+        eprintln!("Failed to initialize tracing subscriber: {}", e);
+    }
+
+    let container = testcontainers_modules::clickhouse::ClickHouse::default()
+        .with_tag("latest")
+        .with_env_var("CLICKHOUSE_USER", USER)
+        .with_env_var("CLICKHOUSE_PASSWORD", PASSWORD)
+        .with_env_var("CLICKHOUSE_DB", DATABASE)
+        .start()
+        .await
+        .expect("Failed to start ClickHouse container");
+    let host = container
+        .get_host()
+        .await
+        .expect("Failed to get ClickHouse host");
+    let port = container
+        .get_host_port_ipv4(8123)
+        .await
+        .expect("Failed to get ClickHouse port");
+
+    let url = format!("http://{host}:{port}");
+
+    let client = ::db::DatabaseConfig::new(
+        url.clone(),
+        USER.to_string(),
+        PASSWORD.to_string(),
+        DATABASE.to_string(),
+    )
+    .create_client();
+    ::db::to(&client, MIGRATIONS_DIRECTORY, "latest").await?;
+
+    Ok(ClickHouse {
+        _container: container,
+        url,
+        client,
+    })
+}
+
+fn config(url: &str, mock: &PoeMock, dead_letter_dir: &Path) -> Result<Arc<Config>> {
+    let contents = format!(
+        r#"
+        [clickhouse]
+        url = "{url}"
+        user = "{USER}"
+        password = "{PASSWORD}"
+        database = "{DATABASE}"
+
+        [oauth]
+        client_id = "client_id"
+        client_secret = "client_secret"
+        token_url = "{token_url}"
+
+        [token_cache]
+        backend = "memory"
+
+        [crawler]
+        api_url = "{api_url}"
+        stats_file = "../../data/stats.json"
+        currencies_file = "../../data/currencies.json"
+
+        [sinks]
+        dead_letter_dir = "{dead_letter_dir}"
+        "#,
+        token_url = mock.token_url(),
+        api_url = mock.api_url(),
+        dead_letter_dir = dead_letter_dir.display(),
+    );
+    Ok(Arc::new(Config::from_sources(Some(&contents), |_| None)?))
+}
+
+#[tokio::test]
+#[serial]
+async fn test_crawl_pipeline() -> Result<()> {
+    let ClickHouse {
+        _container,
+        url,
+        client: clickhouse,
+    } = clickhouse().await?;
+
+    let mock = PoeMock::with_fixtures().await?;
+    let dead_letter_dir = std::env::temp_dir().join(format!(
+        "pashe-pipeline-dead-letters-{}",
+        std::process::id()
+    ));
+    let config = config(&url, &mock, &dead_letter_dir)?;
+
+    // Xbox has no poe.ninja source, so a fresh database starts from the beginning of the mock
+    let db = db::Client::new(&url, USER, PASSWORD, DATABASE);
+    let shutdown_token = CancellationToken::new();
+    let crawl = tokio::spawn(crawler::crawl(
+        db.clone(),
+        None,
+        config,
+        &[Realm::Xbox],
+        Arc::new(Health::new()),
+        shutdown_token.clone(),
+    ));
+
+    // The last fixture page points to the head of the stream
+    let mut checkpoint = None;
+    for _ in 0..100 {
+        checkpoint = db.last_checkpoint("xbox").await?;
+        if checkpoint.as_deref() == Some("3-3-3-3-3") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    shutdown_token.cancel();
+    crawl.await??;
+    assert_eq!(checkpoint.as_deref(), Some("3-3-3-3-3"));
+
+    // Three priced items on the first page, one repriced on the second
+    let items: u64 = clickhouse
+        .query("SELECT count() FROM items WHERE realm = 'xbox'")
+        .fetch_one()
+        .await?;
+    assert_eq!(items, 4);
+
+    let explicit_mods: Vec<String> = clickhouse
+        .query("SELECT explicit_mods FROM current_listings FINAL WHERE realm = 'xbox' AND item_id = 'item-a1'")
+        .fetch_one()
+        .await?;
+    assert_eq!(
+        explicit_mods,
+        ["+92 to maximum Energy Shield", "+45 to maximum Life"]
+    );
+
+    let stat_ids: Vec<String> = clickhouse
+        .query("SELECT stat_ids FROM current_listings FINAL WHERE realm = 'xbox' AND item_id = 'item-a1'")
+        .fetch_one()
+        .await?;
+    assert!(
+        stat_ids.contains(&"explicit.stat_3299347043".to_string()),
+        "{stat_ids:?}"
+    );
+
+    // The first item has +45 to maximum Life, see the fixtures
+    let total_life: f32 = clickhouse
+        .query("SELECT pseudo_total_life FROM current_listings FINAL WHERE realm = 'xbox' AND item_id = 'item-a1'")
+        .fetch_one()
+        .await?;
+    assert_eq!(total_life, 45.0);
+
+    let listing_type: String = clickhouse
+        .query("SELECT toString(listing_type) FROM current_listings FINAL WHERE realm = 'xbox' AND item_id = 'item-a1'")
+        .fetch_one()
+        .await?;
+    assert_eq!(listing_type, "buyout");
+
+    let removed: u64 = clickhouse
+        .query("SELECT count() FROM stash_events WHERE realm = 'xbox' AND item_id = 'item-a2' AND kind = 'removed'")
+        .fetch_one()
+        .await?;
+    assert_eq!(removed, 1);
+
+    // A page inserted again after a failure keeps a single copy of its rows
+    let events = [db::StashEvent {
+        timestamp: Utc::now(),
+        realm: "xbox".to_string(),
+        league: "Standard".to_string(),
+        stash_id: "stash-replayed".to_string(),
+        item_id: "item-replayed".to_string(),
+        kind: db::StashEventKind::Added,
+        price_quantity: 1.0,
+        price_currency: "chaos".to_string(),
+        previous_price_quantity: 0.0,
+        previous_price_currency: String::new(),
+    }];
+    db.insert_stash_events(&events, "xbox/replayed").await?;
+    db.insert_stash_events(&events, "xbox/replayed").await?;
+    let replayed: u64 = clickhouse
+        .query("SELECT count() FROM stash_events WHERE stash_id = 'stash-replayed'")
+        .fetch_one()
+        .await?;
+    assert_eq!(replayed, 1);
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].path, "/public-stash-tabs/xbox");
+    assert_eq!(requests[0].change_id, None);
+
+    ::db::reset(&clickhouse, true).await?;
+    std::fs::remove_dir_all(dead_letter_dir).ok();
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn test_crawl_stops_when_a_change_id_cant_be_fetched() -> Result<()> {
+    let ClickHouse {
+        _container,
+        url,
+        client: clickhouse,
+    } = clickhouse().await?;
+
+    let mock = PoeMock::with_fixtures().await?;
+    mock.push_fault(Fault::Status(400));
+    let dead_letter_dir = std::env::temp_dir().join(format!(
+        "pashe-pipeline-bad-request-dead-letters-{}",
+        std::process::id()
+    ));
+    let config = config(&url, &mock, &dead_letter_dir)?;
+
+    // Nothing cancels the token, the crawl has to stop by itself
+    let db = db::Client::new(&url, USER, PASSWORD, DATABASE);
+    let shutdown_token = CancellationToken::new();
+    let result = tokio::time::timeout(
+        Duration::from_secs(30),
+        crawler::crawl(
+            db.clone(),
+            None,
+            config,
+            &[Realm::Xbox],
+            Arc::new(Health::new()),
+            shutdown_token.clone(),
+        ),
+    )
+    .await
+    .expect("The crawl kept running after a bad request");
+
+    let error = result.expect_err("A bad request should fail the crawl");
+    assert!(format!("{error:#}").contains("xbox"), "{error:#}");
+    assert!(shutdown_token.is_cancelled());
+    assert_eq!(db.last_checkpoint("xbox").await?, None);
+
+    ::db::reset(&clickhouse, true).await?;
+    std::fs::remove_dir_all(dead_letter_dir).ok();
+    Ok(())
+}
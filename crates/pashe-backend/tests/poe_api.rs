@@ -0,0 +1,310 @@
+use anyhow::Result;
+use pashe_backend::cache::MemoryTokenCache;
+use pashe_backend::health::Health;
+use pashe_backend::poe::authorization::{AuthorizationMiddleware, fetch_access_token};
+use pashe_backend::poe::dead_letter;
+use pashe_backend::poe::public_stash_worker::{PublicStashWorker, StashPage};
+use pashe_backend::poe::rate_limit::{MemoryStore, RateLimitMiddleware};
+use pashe_backend::poe::realm::Realm;
+use pashe_backend::poe::retry::RetryMiddleware;
+use pashe_config::{Config, RateLimitPacingConfig};
+use poe_mock::{Fault, PoeMock, RateLimit};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+fn config(mock: &PoeMock) -> Arc<Config> {
+    let contents = format!(
+        r#"
+        [oauth]
+        client_id = "client_id"
+        client_secret = "client_secret"
+        token_url = "{}"
+        "#,
+        mock.token_url()
+    );
+    Arc::new(Config::from_sources(Some(&contents), |_| None).unwrap())
+}
+
+/// Client authorized with a token of the mock, going through `middleware` first
+async fn client(mock: &PoeMock, middleware: Option<RetryMiddleware>) -> ClientWithMiddleware {
+    let config = config(mock);
+    let http_client = reqwest::Client::new();
+    let access_token = fetch_access_token(&http_client, config.oauth().unwrap())
+        .await
+        .unwrap();
+
+    let authorization = AuthorizationMiddleware::new(
+        http_client.clone(),
+        config,
+        Arc::new(MemoryTokenCache::default()),
+        Arc::new(Health::new()),
+        access_token.secret,
+    );
+
+    let mut builder = ClientBuilder::new(http_client);
+    if let Some(middleware) = middleware {
+        builder = builder.with(middleware);
+    }
+    builder
+        .with(authorization)
+        .with(RateLimitMiddleware::new(
+            CancellationToken::new(),
+            Arc::new(MemoryStore::default()),
+            RateLimitPacingConfig::Burst,
+        ))
+        .build()
+}
+
+fn worker(mock: &PoeMock) -> Arc<PublicStashWorker> {
+    Arc::new(
+        PublicStashWorker::new(CancellationToken::new(), Realm::Pc, Arc::new(Health::new()))
+            .with_api_url(mock.api_url()),
+    )
+}
+
+fn statuses(mock: &PoeMock) -> Vec<u16> {
+    mock.requests()
+        .iter()
+        .map(|request| request.status)
+        .collect()
+}
+
+/// Fetches a page with the worker, returning its result, the next change ID it queued and the
+/// stashes it decoded
+async fn fetch(
+    worker: Arc<PublicStashWorker>,
+    client: ClientWithMiddleware,
+    change_id: &str,
+) -> (Result<()>, Option<(u64, String)>, Option<usize>) {
+    let (next_change_id_tx, mut next_change_id_rx) = mpsc::channel(1);
+    let (stash_changes_tx, mut stash_changes_rx) = mpsc::channel::<StashPage>(1);
+
+    let fetch = tokio::spawn(worker.fetch_stash(
+        Arc::new(client),
+        0,
+        change_id.to_string(),
+        next_change_id_tx,
+        stash_changes_tx,
+    ));
+
+    // Pages that failed to decode are discarded, like the processor does
+    let mut stash_count = None;
+    while let Some(mut page) = stash_changes_rx.recv().await {
+        let mut count = 0;
+        while page.stashes.recv().await.is_some() {
+            count += 1;
+        }
+        if page.summary.await.is_ok() {
+            stash_count = Some(count);
+            break;
+        }
+    }
+
+    let result = fetch.await.unwrap();
+    (result, next_change_id_rx.recv().await, stash_count)
+}
+
+#[tokio::test]
+async fn fetches_an_access_token() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+
+    let access_token = fetch_access_token(&reqwest::Client::new(), config(&mock).oauth().unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(access_token.secret, "token-1");
+    assert_eq!(access_token.expires_in, Some(Duration::from_secs(3600)));
+}
+
+#[tokio::test]
+async fn refreshes_rejected_tokens() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    mock.revoke_tokens();
+
+    let response = client
+        .get(format!("{}/public-stash-tabs", mock.api_url()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(mock.issued_tokens(), 2);
+    assert_eq!(statuses(&mock), [401, 200]);
+    assert_eq!(
+        mock.requests()[1].authorization.as_deref(),
+        Some("Bearer token-2")
+    );
+}
+
+#[tokio::test]
+async fn fetch_stash_follows_the_change_id_chain() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+
+    let (result, next_change_id, stash_count) = fetch(worker(&mock), client, "").await;
+
+    result.unwrap();
+    assert_eq!(next_change_id, Some((1, "1-1-1-1-1".to_string())));
+    assert_eq!(stash_count, Some(2));
+}
+
+#[tokio::test]
+async fn fetch_stash_fetches_pages_cut_short_again() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    mock.push_fault(Fault::Truncated);
+
+    let (result, next_change_id, stash_count) = fetch(worker(&mock), client, "").await;
+
+    result.unwrap();
+    // The next change ID is only sent once, by the first attempt
+    assert_eq!(next_change_id, Some((1, "1-1-1-1-1".to_string())));
+    assert_eq!(stash_count, Some(2));
+    assert_eq!(statuses(&mock), [200, 200]);
+}
+
+#[tokio::test]
+async fn fetch_stash_reports_malformed_pages() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    // The page is fetched again to be dead-lettered, as it isn't archived
+    mock.push_fault(Fault::Malformed);
+    mock.push_fault(Fault::Malformed);
+
+    let dead_letter_dir: PathBuf =
+        std::env::temp_dir().join(format!("pashe-dead-pages-{}", std::process::id()));
+    let worker = Arc::new(
+        PublicStashWorker::new(CancellationToken::new(), Realm::Pc, Arc::new(Health::new()))
+            .with_api_url(mock.api_url())
+            .with_dead_letter_dir(dead_letter_dir.clone()),
+    );
+
+    let (result, next_change_id, stash_count) = fetch(worker, client, "1-1-1-1-1").await;
+
+    result.unwrap();
+    // The chain goes on from the headers, the page is set aside to be retried
+    assert_eq!(next_change_id, Some((1, "2-2-2-2-2".to_string())));
+    assert_eq!(stash_count, None);
+    let pages = dead_letter::list_pages(&dead_letter_dir).unwrap();
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0].0, "1-1-1-1-1");
+    let failure =
+        std::fs::read_to_string(dead_letter_dir.join("pages/1-1-1-1-1.error.json")).unwrap();
+    assert!(failure.contains(r#""path": "stashes[0].id""#), "{failure}");
+    assert_eq!(statuses(&mock), [200, 200]);
+    std::fs::remove_dir_all(dead_letter_dir).unwrap();
+}
+
+#[tokio::test]
+async fn fetch_stash_stops_when_malformed_pages_cant_be_set_aside() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    mock.push_fault(Fault::Malformed);
+
+    let shutdown_token = CancellationToken::new();
+    let worker = Arc::new(
+        PublicStashWorker::new(shutdown_token.clone(), Realm::Pc, Arc::new(Health::new()))
+            .with_api_url(mock.api_url()),
+    );
+
+    let (result, _, stash_count) = fetch(worker, client, "1-1-1-1-1").await;
+
+    // Without a dead-letter directory the page would be lost
+    assert!(result.is_err());
+    assert_eq!(stash_count, None);
+    assert!(shutdown_token.is_cancelled());
+}
+
+#[tokio::test]
+async fn fetch_stash_dead_letters_rejected_change_ids() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    mock.push_fault(Fault::Status(400));
+
+    let dead_letter_dir: PathBuf =
+        std::env::temp_dir().join(format!("pashe-dead-letters-{}", std::process::id()));
+    let worker = Arc::new(
+        PublicStashWorker::new(CancellationToken::new(), Realm::Pc, Arc::new(Health::new()))
+            .with_api_url(mock.api_url())
+            .with_dead_letter_dir(dead_letter_dir.clone()),
+    );
+
+    let (result, next_change_id, _) = fetch(worker, client, "1-1-1-1-1").await;
+
+    assert!(result.is_err());
+    assert_eq!(next_change_id, None);
+    let log = std::fs::read_to_string(dead_letter_dir.join("fetch_failures.jsonl")).unwrap();
+    assert!(log.contains(r#""change_id":"1-1-1-1-1""#), "{log}");
+    std::fs::remove_dir_all(dead_letter_dir).unwrap();
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(
+        &mock,
+        Some(RetryMiddleware::new(CancellationToken::new(), 3)),
+    )
+    .await;
+    mock.push_fault(Fault::Status(503));
+    mock.push_fault(Fault::Status(502));
+
+    let response = client
+        .get(format!("{}/public-stash-tabs", mock.api_url()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(statuses(&mock), [503, 502, 200]);
+}
+
+#[tokio::test]
+async fn retries_too_many_requests_after_the_delay() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    mock.push_fault(Fault::TooManyRequests {
+        retry_after: Some(1),
+    });
+
+    let response = client
+        .get(format!("{}/public-stash-tabs", mock.api_url()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(statuses(&mock), [429, 200]);
+}
+
+#[tokio::test]
+async fn waits_for_the_rate_limit_window_instead_of_exceeding_it() {
+    let mock = PoeMock::with_fixtures().await.unwrap();
+    let client = client(&mock, None).await;
+    mock.set_rate_limit(RateLimit {
+        policy: "stash-request-limit".to_string(),
+        rule: "Client".to_string(),
+        max_hits: 3,
+        period: Duration::from_secs(2),
+        ban: Duration::from_secs(60),
+    });
+
+    let start = std::time::Instant::now();
+    for _ in 0..4 {
+        let response = client
+            .get(format!("{}/public-stash-tabs", mock.api_url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    // One hit of the three is kept in reserve, so the third request waits for the window
+    assert!(start.elapsed() >= Duration::from_secs(2));
+    assert_eq!(statuses(&mock), [200, 200, 200, 200]);
+}
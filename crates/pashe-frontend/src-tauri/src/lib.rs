@@ -1,8 +1,8 @@
 use chrono::{DateTime, Utc};
 use clickhouse::{Client, Row};
+use pashe_config::Config;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use std::env;
 
 /// Period types for statistics aggregation
 #[derive(Debug, Serialize_repr, Deserialize_repr)]
@@ -35,23 +35,19 @@ fn greet(name: &str) -> String {
 
 #[tauri::command]
 async fn get_statistics_per_periods() -> Result<Vec<StatisticsPerPeriod>, String> {
-    // Load environment variables
-    dotenvy::dotenv().expect("Failed to load .env file");
+    // Environment variables override the configuration file, they may come from a .env file
+    let _ = dotenvy::dotenv();
 
-    let clickhouse_url =
-        env::var("CLICKHOUSE_URL").expect("CLICKHOUSE_URL must be set in .env file");
-    let clickhouse_user =
-        env::var("CLICKHOUSE_USER").expect("CLICKHOUSE_USER must be set in .env file");
-    let clickhouse_password =
-        env::var("CLICKHOUSE_PASSWORD").expect("CLICKHOUSE_PASSWORD must be set in .env file");
-    let clickhouse_database =
-        env::var("CLICKHOUSE_DATABASE").expect("CLICKHOUSE_DATABASE must be set in .env file");
+    let config = Config::load(None).map_err(|e| format!("Configuration error: {e}"))?;
+    let clickhouse = config
+        .clickhouse()
+        .map_err(|e| format!("Configuration error: {e}"))?;
 
     let client = Client::default()
-        .with_url(&clickhouse_url)
-        .with_user(&clickhouse_user)
-        .with_password(&clickhouse_password)
-        .with_database(&clickhouse_database);
+        .with_url(&clickhouse.url)
+        .with_user(&clickhouse.user)
+        .with_password(&clickhouse.password)
+        .with_database(&clickhouse.database);
 
     let query = r#"
         SELECT
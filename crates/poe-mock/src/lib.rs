@@ -0,0 +1,393 @@
+//! Mock of the Path of Exile API, for integration tests.
+//!
+//! Serves the OAuth token endpoint, and the public stash endpoint from a chain of fixture pages.
+//! Tests can make it enforce a rate limit rule, answer with 429s, server errors or malformed
+//! pages, and revoke the tokens it issued.
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Lifetime of the issued access tokens, in seconds
+const TOKEN_LIFETIME: u64 = 3600;
+
+/// A page of the public stash endpoint
+#[derive(Debug, Clone)]
+pub struct Page {
+    /// Change ID the page is served for, empty for the start of the stream
+    pub change_id: String,
+    pub next_change_id: String,
+    /// JSON body of the page
+    pub body: Vec<u8>,
+}
+
+/// Directory of the fixture pages shipped with this crate
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+/// Loads the `*.json` pages of a directory as a change ID chain, in file name order
+pub fn load_fixtures(directory: &Path) -> io::Result<Vec<Page>> {
+    let mut paths = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "json")
+    });
+    paths.sort();
+
+    let mut change_id = String::new();
+    paths
+        .into_iter()
+        .map(|path| {
+            let body = std::fs::read(&path)?;
+            let next_change_id =
+                serde_json::from_slice::<serde_json::Value>(&body)?["next_change_id"]
+                    .as_str()
+                    .ok_or_else(|| {
+                        io::Error::other(format!("{} has no next_change_id", path.display()))
+                    })?
+                    .to_string();
+
+            let page = Page {
+                change_id: std::mem::replace(&mut change_id, next_change_id.clone()),
+                next_change_id,
+                body,
+            };
+            Ok(page)
+        })
+        .collect()
+}
+
+/// Unusual answer to the next request of the public stash endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// 429, with a `Retry-After` header when given
+    TooManyRequests { retry_after: Option<u64> },
+    /// Empty response with the given status
+    Status(u16),
+    /// The page cut short in the middle of a stash, with a valid next change ID header
+    Malformed,
+    /// The page served in full, but its compressed body ends halfway as if the connection dropped
+    Truncated,
+}
+
+/// Rate limit rule applied to the public stash endpoint, counted over a rolling window
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    pub policy: String,
+    pub rule: String,
+    pub max_hits: u32,
+    pub period: Duration,
+    /// Restriction applied when the rule is exceeded
+    pub ban: Duration,
+}
+
+/// A request received by the public stash endpoint
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub path: String,
+    pub change_id: Option<String>,
+    pub authorization: Option<String>,
+    pub status: u16,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    pages: HashMap<String, Page>,
+    /// Next change ID of the last page, served as an empty page like the head of the stream
+    head_change_id: String,
+    faults: VecDeque<Fault>,
+    rate_limit: Option<RateLimit>,
+    hits: Vec<Instant>,
+    banned_until: Option<Instant>,
+    issued_tokens: u32,
+    valid_tokens: HashSet<String>,
+    requests: Vec<RecordedRequest>,
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+/// A running mock server, stopped when dropped
+#[derive(Debug)]
+pub struct PoeMock {
+    address: SocketAddr,
+    state: SharedState,
+    server: JoinHandle<()>,
+}
+
+impl PoeMock {
+    /// Serves the pages on a random local port
+    pub async fn start(pages: Vec<Page>) -> io::Result<Self> {
+        let head_change_id = pages
+            .last()
+            .map(|page| page.next_change_id.clone())
+            .unwrap_or_default();
+        let state = Arc::new(Mutex::new(MockState {
+            pages: pages
+                .into_iter()
+                .map(|page| (page.change_id.clone(), page))
+                .collect(),
+            head_change_id,
+            ..MockState::default()
+        }));
+
+        let router = Router::new()
+            .route("/oauth/token", post(issue_token))
+            .route("/public-stash-tabs", get(public_stash_tabs))
+            .route("/public-stash-tabs/{realm}", get(public_stash_tabs))
+            .with_state(Arc::clone(&state));
+
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            axum::serve(listener, router)
+                .await
+                .expect("Mock server failed");
+        });
+
+        Ok(Self {
+            address,
+            state,
+            server,
+        })
+    }
+
+    /// Serves the fixture pages shipped with this crate
+    pub async fn with_fixtures() -> io::Result<Self> {
+        Self::start(load_fixtures(&fixtures_dir())?).await
+    }
+
+    /// Base URL of the API, to use in place of `https://api.pathofexile.com`
+    pub fn api_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// URL of the OAuth token endpoint
+    pub fn token_url(&self) -> String {
+        format!("http://{}/oauth/token", self.address)
+    }
+
+    /// Enforces a rate limit rule on the public stash endpoint and reports it in the headers
+    pub fn set_rate_limit(&self, rate_limit: RateLimit) {
+        self.state.lock().unwrap().rate_limit = Some(rate_limit);
+    }
+
+    /// Answers the next request of the public stash endpoint with a fault, faults are used in
+    /// the order they were pushed
+    pub fn push_fault(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    /// Rejects every token issued so far
+    pub fn revoke_tokens(&self) {
+        self.state.lock().unwrap().valid_tokens.clear();
+    }
+
+    /// Number of tokens issued so far
+    pub fn issued_tokens(&self) -> u32 {
+        self.state.lock().unwrap().issued_tokens
+    }
+
+    /// Requests received by the public stash endpoint, in order
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for PoeMock {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn issue_token(State(state): State<SharedState>) -> impl IntoResponse {
+    let mut state = state.lock().unwrap();
+    state.issued_tokens += 1;
+    let access_token = format!("token-{}", state.issued_tokens);
+    state.valid_tokens.insert(access_token.clone());
+
+    axum::Json(serde_json::json!({
+        "access_token": access_token,
+        "expires_in": TOKEN_LIFETIME,
+        "token_type": "bearer",
+        "scope": "service:psapi",
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct StashQuery {
+    id: Option<String>,
+}
+
+async fn public_stash_tabs(
+    State(state): State<SharedState>,
+    Query(query): Query<StashQuery>,
+    uri: axum::http::Uri,
+    headers: HeaderMap,
+) -> Response {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let mut state = state.lock().unwrap();
+    let response = respond(&mut state, query.id.as_deref(), authorization.as_deref());
+    state.requests.push(RecordedRequest {
+        path: uri.path().to_string(),
+        change_id: query.id,
+        authorization,
+        status: response.status().as_u16(),
+    });
+
+    response
+}
+
+fn respond(
+    state: &mut MockState,
+    change_id: Option<&str>,
+    authorization: Option<&str>,
+) -> Response {
+    let authorized = authorization
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .is_some_and(|token| state.valid_tokens.contains(token));
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let (rate_limit_headers, banned) = rate_limit(state);
+    if banned {
+        return (rate_limit_headers, StatusCode::TOO_MANY_REQUESTS).into_response();
+    }
+
+    let change_id = change_id.unwrap_or_default();
+    let page = match state.pages.get(change_id) {
+        Some(page) => page.clone(),
+        None if change_id == state.head_change_id => Page {
+            change_id: change_id.to_string(),
+            next_change_id: change_id.to_string(),
+            body: serde_json::to_vec(&serde_json::json!({
+                "next_change_id": change_id,
+                "stashes": [],
+            }))
+            .unwrap(),
+        },
+        None => return (rate_limit_headers, StatusCode::BAD_REQUEST).into_response(),
+    };
+
+    let fault = state.faults.pop_front();
+    let body = match fault {
+        None | Some(Fault::Truncated) => page.body,
+        Some(Fault::Malformed) => {
+            let stashes_start = page
+                .body
+                .windows(b"\"stashes\"".len())
+                .position(|window| window == b"\"stashes\"")
+                .unwrap_or(0);
+            [&page.body[..stashes_start], b"\"stashes\": [{\"id\": "].concat()
+        }
+        Some(Fault::TooManyRequests { retry_after }) => {
+            let mut headers = rate_limit_headers;
+            if let Some(retry_after) = retry_after {
+                headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            }
+            return (headers, StatusCode::TOO_MANY_REQUESTS).into_response();
+        }
+        Some(Fault::Status(status)) => {
+            let status = StatusCode::from_u16(status).expect("Invalid fault status");
+            return (rate_limit_headers, status).into_response();
+        }
+    };
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&body).unwrap();
+    let mut compressed_body = encoder.finish().unwrap();
+    if let Some(Fault::Truncated) = fault {
+        compressed_body.truncate(compressed_body.len() / 2);
+    }
+
+    let mut headers = rate_limit_headers;
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.insert(
+        "x-next-change-id",
+        HeaderValue::from_str(&page.next_change_id).unwrap(),
+    );
+
+    (headers, Body::from(compressed_body)).into_response()
+}
+
+/// Counts the request against the rate limit rule, returning its headers and whether the
+/// client is banned for exceeding it
+fn rate_limit(state: &mut MockState) -> (HeaderMap, bool) {
+    let mut headers = HeaderMap::new();
+    let Some(rate_limit) = state.rate_limit.clone() else {
+        return (headers, false);
+    };
+
+    let now = Instant::now();
+    state
+        .hits
+        .retain(|&hit| now.duration_since(hit) < rate_limit.period);
+    state.hits.push(now);
+    if state.banned_until.is_none() && state.hits.len() as u32 > rate_limit.max_hits {
+        state.banned_until = Some(now + rate_limit.ban);
+    }
+    let active_ban = state
+        .banned_until
+        .map(|banned_until| banned_until.saturating_duration_since(now))
+        .filter(|active_ban| !active_ban.is_zero());
+    if active_ban.is_none() {
+        state.banned_until = None;
+    }
+
+    let RateLimit {
+        policy,
+        rule,
+        max_hits,
+        period,
+        ban,
+    } = &rate_limit;
+    let header = |value: String| HeaderValue::from_str(&value).unwrap();
+    headers.insert("x-rate-limit-policy", header(policy.clone()));
+    headers.insert("x-rate-limit-rules", header(rule.clone()));
+    headers.insert(
+        axum::http::HeaderName::try_from(format!("x-rate-limit-{rule}").to_lowercase()).unwrap(),
+        header(format!("{max_hits}:{}:{}", period.as_secs(), ban.as_secs())),
+    );
+    headers.insert(
+        axum::http::HeaderName::try_from(format!("x-rate-limit-{rule}-state").to_lowercase())
+            .unwrap(),
+        header(format!(
+            "{}:{}:{}",
+            state.hits.len(),
+            period.as_secs(),
+            active_ban.map_or(0, |active_ban| active_ban.as_secs().max(1))
+        )),
+    );
+
+    if let Some(active_ban) = active_ban {
+        headers.insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(active_ban.as_secs().max(1)),
+        );
+    }
+    (headers, active_ban.is_some())
+}
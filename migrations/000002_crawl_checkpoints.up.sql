@@ -0,0 +1,8 @@
+CREATE TABLE crawl_checkpoints
+(
+    `timestamp` DateTime64(3, 'UTC') DEFAULT now64(3),
+    `change_id` String
+)
+ENGINE = MergeTree
+ORDER BY timestamp
+TTL toDateTime(timestamp) + INTERVAL 30 DAY;
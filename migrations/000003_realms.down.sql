@@ -0,0 +1,3 @@
+ALTER TABLE crawl_checkpoints DROP COLUMN `realm`;
+ALTER TABLE statistics_events DROP COLUMN `realm`;
+ALTER TABLE items DROP COLUMN `realm`;
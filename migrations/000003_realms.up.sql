@@ -0,0 +1,3 @@
+ALTER TABLE items ADD COLUMN `realm` LowCardinality(String) DEFAULT 'pc' AFTER `timestamp`;
+ALTER TABLE statistics_events ADD COLUMN `realm` LowCardinality(String) DEFAULT 'pc' AFTER `timestamp`;
+ALTER TABLE crawl_checkpoints ADD COLUMN `realm` LowCardinality(String) DEFAULT 'pc' AFTER `timestamp`;
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS stash_events;
\ No newline at end of file
@@ -0,0 +1,16 @@
+CREATE TABLE stash_events
+(
+    `timestamp` DateTime('UTC') DEFAULT now() CODEC(Delta(4), ZSTD(1)),
+    `realm` LowCardinality(String),
+    `league` LowCardinality(String),
+    `stash_id` String,
+    `item_id` String,
+    `kind` Enum8('added' = 1, 'removed' = 2, 'repriced' = 3),
+    `price_quantity` Float32,
+    `price_currency` LowCardinality(String),
+    `previous_price_quantity` Float32,
+    `previous_price_currency` LowCardinality(String)
+)
+ENGINE = MergeTree
+PARTITION BY (league, toYYYYMM(timestamp))
+ORDER BY (realm, league, item_id, timestamp);
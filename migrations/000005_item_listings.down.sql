@@ -0,0 +1 @@
+DROP TABLE IF EXISTS item_listings;
\ No newline at end of file
@@ -0,0 +1,18 @@
+CREATE TABLE item_listings
+(
+    `realm` LowCardinality(String),
+    `league` LowCardinality(String),
+    `item_id` String,
+    `base` SimpleAggregateFunction(max, LowCardinality(String)),
+    `name` SimpleAggregateFunction(max, LowCardinality(String)),
+    `frame_type` SimpleAggregateFunction(max, UInt8),
+    `first_seen` SimpleAggregateFunction(min, DateTime('UTC')),
+    `last_seen` SimpleAggregateFunction(max, DateTime('UTC')),
+    `removed_at` SimpleAggregateFunction(max, DateTime('UTC')),
+    `price_changes` SimpleAggregateFunction(sum, UInt32),
+    `first_price` SimpleAggregateFunction(min, Tuple(UInt32, Float32, String)),
+    `last_price` SimpleAggregateFunction(max, Tuple(UInt32, Float32, String))
+)
+ENGINE = AggregatingMergeTree
+PARTITION BY league
+ORDER BY (realm, league, item_id);
@@ -0,0 +1,5 @@
+DROP VIEW IF EXISTS current_listings_removals_mv;
+DROP VIEW IF EXISTS current_listings_items_mv;
+DROP TABLE IF EXISTS current_listings;
+ALTER TABLE items DROP COLUMN `item_id`;
+ALTER TABLE items DROP COLUMN `stash_id`;
@@ -0,0 +1,75 @@
+ALTER TABLE items ADD COLUMN `stash_id` String DEFAULT '' AFTER `league`;
+ALTER TABLE items ADD COLUMN `item_id` String DEFAULT '' AFTER `stash_id`;
+
+-- Latest observation of every listing, removed listings are dropped when read with FINAL.
+-- The version orders observations by time, an addition winning over a removal at the same time
+-- so that items moved between stashes stay listed.
+CREATE TABLE current_listings
+(
+    `version` UInt64,
+    `deleted` UInt8,
+    `timestamp` DateTime('UTC'),
+    `realm` LowCardinality(String),
+    `league` LowCardinality(String),
+    `stash_id` String,
+    `item_id` String,
+    `base` LowCardinality(String),
+    `name` LowCardinality(String),
+    `links` UInt8,
+    `ilvl` UInt8,
+    `frame_type` UInt8,
+    `corrupted` Bool,
+    `stack_size` UInt16,
+    `level` UInt8,
+    `quality` UInt8,
+    `passives` UInt8,
+    `tier` UInt8,
+    `influences` Array(LowCardinality(String)),
+    `price_quantity` Float32,
+    `price_currency` LowCardinality(String)
+)
+ENGINE = ReplacingMergeTree(version, deleted)
+PARTITION BY league
+ORDER BY (realm, league, item_id);
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
+
+CREATE MATERIALIZED VIEW current_listings_removals_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 AS version,
+    1 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id
+FROM stash_events
+WHERE kind = 'removed';
@@ -0,0 +1,54 @@
+-- Restores the definition of the view from 000006_current_listings.up.sql
+DROP VIEW IF EXISTS current_listings_items_mv;
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
+
+ALTER TABLE current_listings
+    DROP COLUMN `enchant_mods`,
+    DROP COLUMN `implicit_mods`,
+    DROP COLUMN `explicit_mods`,
+    DROP COLUMN `crafted_mods`,
+    DROP COLUMN `fractured_mods`,
+    DROP COLUMN `veiled_mods`,
+    DROP COLUMN `scourge_mods`,
+    DROP COLUMN `crucible_mods`,
+    DROP COLUMN `utility_mods`,
+    DROP COLUMN `rune_mods`;
+
+ALTER TABLE items
+    DROP COLUMN `enchant_mods`,
+    DROP COLUMN `implicit_mods`,
+    DROP COLUMN `explicit_mods`,
+    DROP COLUMN `crafted_mods`,
+    DROP COLUMN `fractured_mods`,
+    DROP COLUMN `veiled_mods`,
+    DROP COLUMN `scourge_mods`,
+    DROP COLUMN `crucible_mods`,
+    DROP COLUMN `utility_mods`,
+    DROP COLUMN `rune_mods`;
@@ -0,0 +1,67 @@
+-- Modifier lists of the listed items, each mod as displayed in game (e.g. "+45 to maximum Life").
+-- Cosmetic mods are left out as they have no bearing on the price.
+ALTER TABLE items
+    ADD COLUMN `enchant_mods` Array(String) CODEC(ZSTD(1)) AFTER `influences`,
+    ADD COLUMN `implicit_mods` Array(String) CODEC(ZSTD(1)) AFTER `enchant_mods`,
+    ADD COLUMN `explicit_mods` Array(String) CODEC(ZSTD(1)) AFTER `implicit_mods`,
+    ADD COLUMN `crafted_mods` Array(String) CODEC(ZSTD(1)) AFTER `explicit_mods`,
+    ADD COLUMN `fractured_mods` Array(String) CODEC(ZSTD(1)) AFTER `crafted_mods`,
+    ADD COLUMN `veiled_mods` Array(String) CODEC(ZSTD(1)) AFTER `fractured_mods`,
+    ADD COLUMN `scourge_mods` Array(String) CODEC(ZSTD(1)) AFTER `veiled_mods`,
+    ADD COLUMN `crucible_mods` Array(String) CODEC(ZSTD(1)) AFTER `scourge_mods`,
+    ADD COLUMN `utility_mods` Array(String) CODEC(ZSTD(1)) AFTER `crucible_mods`,
+    ADD COLUMN `rune_mods` Array(String) CODEC(ZSTD(1)) AFTER `utility_mods`;
+
+ALTER TABLE current_listings
+    ADD COLUMN `enchant_mods` Array(String) AFTER `influences`,
+    ADD COLUMN `implicit_mods` Array(String) AFTER `enchant_mods`,
+    ADD COLUMN `explicit_mods` Array(String) AFTER `implicit_mods`,
+    ADD COLUMN `crafted_mods` Array(String) AFTER `explicit_mods`,
+    ADD COLUMN `fractured_mods` Array(String) AFTER `crafted_mods`,
+    ADD COLUMN `veiled_mods` Array(String) AFTER `fractured_mods`,
+    ADD COLUMN `scourge_mods` Array(String) AFTER `veiled_mods`,
+    ADD COLUMN `crucible_mods` Array(String) AFTER `scourge_mods`,
+    ADD COLUMN `utility_mods` Array(String) AFTER `crucible_mods`,
+    ADD COLUMN `rune_mods` Array(String) AFTER `utility_mods`;
+
+-- Recreated to carry the mods over to the current listings
+-- Superseded, the current definition of the view is in 000011_currency_catalogue.up.sql
+DROP VIEW IF EXISTS current_listings_items_mv;
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    enchant_mods,
+    implicit_mods,
+    explicit_mods,
+    crafted_mods,
+    fractured_mods,
+    veiled_mods,
+    scourge_mods,
+    crucible_mods,
+    utility_mods,
+    rune_mods,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
@@ -0,0 +1,48 @@
+-- Restores the definition of the view from 000007_item_mods.up.sql
+DROP VIEW IF EXISTS current_listings_items_mv;
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    enchant_mods,
+    implicit_mods,
+    explicit_mods,
+    crafted_mods,
+    fractured_mods,
+    veiled_mods,
+    scourge_mods,
+    crucible_mods,
+    utility_mods,
+    rune_mods,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
+
+ALTER TABLE current_listings
+    DROP COLUMN `stat_ids`,
+    DROP COLUMN `stat_values`;
+
+ALTER TABLE items
+    DROP COLUMN `stat_ids`,
+    DROP COLUMN `stat_values`;
@@ -0,0 +1,52 @@
+-- Trade site stats matched from the mods, each with the values pulled out of the mod text
+ALTER TABLE items
+    ADD COLUMN `stat_ids` Array(LowCardinality(String)) AFTER `rune_mods`,
+    ADD COLUMN `stat_values` Array(Array(Float32)) AFTER `stat_ids`;
+
+ALTER TABLE current_listings
+    ADD COLUMN `stat_ids` Array(LowCardinality(String)) AFTER `rune_mods`,
+    ADD COLUMN `stat_values` Array(Array(Float32)) AFTER `stat_ids`;
+
+-- Recreated to carry the stats over to the current listings
+-- Superseded, the current definition of the view is in 000011_currency_catalogue.up.sql
+DROP VIEW IF EXISTS current_listings_items_mv;
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    enchant_mods,
+    implicit_mods,
+    explicit_mods,
+    crafted_mods,
+    fractured_mods,
+    veiled_mods,
+    scourge_mods,
+    crucible_mods,
+    utility_mods,
+    rune_mods,
+    stat_ids,
+    stat_values,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
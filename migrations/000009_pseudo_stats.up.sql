@@ -0,0 +1,78 @@
+-- Totals derived from the stats, computed when the items are ingested
+ALTER TABLE items
+    ADD COLUMN `pseudo_total_life` Float32 AFTER `stat_values`,
+    ADD COLUMN `pseudo_total_mana` Float32 AFTER `pseudo_total_life`,
+    ADD COLUMN `pseudo_total_energy_shield` Float32 AFTER `pseudo_total_mana`,
+    ADD COLUMN `pseudo_total_elemental_resistance` Float32 AFTER `pseudo_total_energy_shield`,
+    ADD COLUMN `pseudo_total_chaos_resistance` Float32 AFTER `pseudo_total_elemental_resistance`,
+    ADD COLUMN `pseudo_total_strength` Float32 AFTER `pseudo_total_chaos_resistance`,
+    ADD COLUMN `pseudo_total_dexterity` Float32 AFTER `pseudo_total_strength`,
+    ADD COLUMN `pseudo_total_intelligence` Float32 AFTER `pseudo_total_dexterity`,
+    ADD COLUMN `pseudo_open_prefixes` UInt8 AFTER `pseudo_total_intelligence`,
+    ADD COLUMN `pseudo_open_suffixes` UInt8 AFTER `pseudo_open_prefixes`;
+
+ALTER TABLE current_listings
+    ADD COLUMN `pseudo_total_life` Float32 AFTER `stat_values`,
+    ADD COLUMN `pseudo_total_mana` Float32 AFTER `pseudo_total_life`,
+    ADD COLUMN `pseudo_total_energy_shield` Float32 AFTER `pseudo_total_mana`,
+    ADD COLUMN `pseudo_total_elemental_resistance` Float32 AFTER `pseudo_total_energy_shield`,
+    ADD COLUMN `pseudo_total_chaos_resistance` Float32 AFTER `pseudo_total_elemental_resistance`,
+    ADD COLUMN `pseudo_total_strength` Float32 AFTER `pseudo_total_chaos_resistance`,
+    ADD COLUMN `pseudo_total_dexterity` Float32 AFTER `pseudo_total_strength`,
+    ADD COLUMN `pseudo_total_intelligence` Float32 AFTER `pseudo_total_dexterity`,
+    ADD COLUMN `pseudo_open_prefixes` UInt8 AFTER `pseudo_total_intelligence`,
+    ADD COLUMN `pseudo_open_suffixes` UInt8 AFTER `pseudo_open_prefixes`;
+
+-- Recreated to carry the pseudo stats over to the current listings
+-- Superseded, the current definition of the view is in 000011_currency_catalogue.up.sql
+DROP VIEW IF EXISTS current_listings_items_mv;
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    enchant_mods,
+    implicit_mods,
+    explicit_mods,
+    crafted_mods,
+    fractured_mods,
+    veiled_mods,
+    scourge_mods,
+    crucible_mods,
+    utility_mods,
+    rune_mods,
+    stat_ids,
+    stat_values,
+    pseudo_total_life,
+    pseudo_total_mana,
+    pseudo_total_energy_shield,
+    pseudo_total_elemental_resistance,
+    pseudo_total_chaos_resistance,
+    pseudo_total_strength,
+    pseudo_total_dexterity,
+    pseudo_total_intelligence,
+    pseudo_open_prefixes,
+    pseudo_open_suffixes,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
@@ -0,0 +1,58 @@
+-- Restores the definition of the view from 000009_pseudo_stats.up.sql
+DROP VIEW IF EXISTS current_listings_items_mv;
+
+CREATE MATERIALIZED VIEW current_listings_items_mv
+TO current_listings
+AS
+SELECT
+    toUInt64(toUnixTimestamp(timestamp)) * 2 + 1 AS version,
+    0 AS deleted,
+    timestamp,
+    realm,
+    league,
+    stash_id,
+    item_id,
+    base,
+    name,
+    links,
+    ilvl,
+    frame_type,
+    corrupted,
+    stack_size,
+    level,
+    quality,
+    passives,
+    tier,
+    influences,
+    enchant_mods,
+    implicit_mods,
+    explicit_mods,
+    crafted_mods,
+    fractured_mods,
+    veiled_mods,
+    scourge_mods,
+    crucible_mods,
+    utility_mods,
+    rune_mods,
+    stat_ids,
+    stat_values,
+    pseudo_total_life,
+    pseudo_total_mana,
+    pseudo_total_energy_shield,
+    pseudo_total_elemental_resistance,
+    pseudo_total_chaos_resistance,
+    pseudo_total_strength,
+    pseudo_total_dexterity,
+    pseudo_total_intelligence,
+    pseudo_open_prefixes,
+    pseudo_open_suffixes,
+    price_quantity,
+    price_currency
+FROM items
+WHERE item_id != '';
+
+ALTER TABLE current_listings
+    DROP COLUMN `listing_type`;
+
+ALTER TABLE items
+    DROP COLUMN `listing_type`;
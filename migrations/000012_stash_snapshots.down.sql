@@ -0,0 +1,2 @@
+ALTER TABLE current_listings
+    DROP INDEX `stash_id_index`;
@@ -0,0 +1,6 @@
+-- Stash snapshots evicted from memory or lost on a restart are restored by stash ID
+ALTER TABLE current_listings
+    ADD INDEX `stash_id_index` stash_id TYPE bloom_filter GRANULARITY 4;
+
+ALTER TABLE current_listings
+    MATERIALIZE INDEX `stash_id_index`;
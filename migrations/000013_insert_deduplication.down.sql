@@ -0,0 +1,8 @@
+ALTER TABLE item_listings
+    RESET SETTING non_replicated_deduplication_window;
+
+ALTER TABLE stash_events
+    RESET SETTING non_replicated_deduplication_window;
+
+ALTER TABLE items
+    RESET SETTING non_replicated_deduplication_window;
@@ -0,0 +1,10 @@
+-- Pages are inserted with a deduplication token, the server only honours it on non-replicated
+-- tables keeping a window of the last inserted blocks
+ALTER TABLE items
+    MODIFY SETTING non_replicated_deduplication_window = 1000;
+
+ALTER TABLE stash_events
+    MODIFY SETTING non_replicated_deduplication_window = 1000;
+
+ALTER TABLE item_listings
+    MODIFY SETTING non_replicated_deduplication_window = 1000;